@@ -0,0 +1,166 @@
+use crate::markdown;
+
+/// The kinds of admonition that can be written, along with their default titles.
+const KINDS: [(&str, &str); 2] = [("note", "Note"), ("warning", "Warning")];
+
+const START_MARKER: &str = "<!-- admonition ";
+const END_MARKER: &str = "<!-- /admonition -->";
+
+/// Mark up the admonitions in Markdown source, so that they can be found once it's parsed.
+///
+/// An admonition starts with a line like `:::note` or `:::warning Custom title`, and ends with a line containing only
+/// `:::`. Both lines are replaced with HTML comments, which comrak treats as HTML blocks that end on the same line, so
+/// the contents of the admonition are still parsed as Markdown. Admonitions that are never closed are closed at the end
+/// of the document. Otherwise, no lines are added or removed.
+pub fn mark(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut depth = 0;
+
+    for (line, is_text) in markdown::source_lines(source) {
+        let trimmed = line.trim();
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+
+        if !is_text {
+            output.push_str(line);
+        } else if let Some((kind, title)) = opening(trimmed) {
+            depth += 1;
+            output.push_str(&format!("{indent}{START_MARKER}{kind} {title} -->{ending}"));
+        } else if depth > 0 && trimmed.len() >= 3 && trimmed.chars().all(|c| c == ':') {
+            depth -= 1;
+            output.push_str(&format!("{indent}{END_MARKER}{ending}"));
+        } else {
+            output.push_str(line);
+        }
+    }
+
+    for _ in 0..depth {
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+
+        output.push_str(END_MARKER);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Turn a marker left behind by `mark` into HTML, returning `None` if `html` isn't a marker.
+pub fn to_html(html: &str) -> Option<String> {
+    let html = html.trim();
+
+    if html == END_MARKER {
+        return Some("</aside>\n".to_owned());
+    }
+
+    let (kind, title) = html
+        .strip_prefix(START_MARKER)?
+        .strip_suffix(" -->")?
+        .split_once(' ')?;
+
+    KINDS.iter().find(|&&(name, _)| name == kind)?;
+
+    Some(format!(
+        "<aside class=\"admonition admonition-{kind}\">\n<p class=\"admonition-title\">{}</p>\n",
+        tera::escape_html(title)
+    ))
+}
+
+/// Parse the (trimmed) line that opens an admonition, returning its kind and title.
+fn opening(line: &str) -> Option<(&'static str, &str)> {
+    let rest = line.strip_prefix(":::")?.trim_start_matches(':').trim();
+    let (kind, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    let &(kind, default_title) = KINDS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(kind))?;
+
+    match title.trim() {
+        "" => Some((kind, default_title)),
+        title => Some((kind, title)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mark_admonitions() {
+        assert_eq!(
+            mark(":::note\nHello\n:::\n"),
+            "<!-- admonition note Note -->\nHello\n<!-- /admonition -->\n"
+        );
+
+        assert_eq!(
+            mark("  ::::WARNING   Mind the gap\r\nHello\r\n  ::::\r\n"),
+            "  <!-- admonition warning Mind the gap -->\r\nHello\r\n  <!-- /admonition -->\r\n"
+        );
+    }
+
+    #[test]
+    fn mark_nested_admonitions() {
+        assert_eq!(
+            mark(":::note\n::::warning Inner\nHello\n::::\nOuter\n:::\n"),
+            "<!-- admonition note Note -->\n<!-- admonition warning Inner -->\nHello\n<!-- /admonition -->\nOuter\n\
+             <!-- /admonition -->\n"
+        );
+    }
+
+    #[test]
+    fn mark_closes_unclosed_admonitions() {
+        assert_eq!(
+            mark(":::note\nHello"),
+            "<!-- admonition note Note -->\nHello\n<!-- /admonition -->\n"
+        );
+
+        assert_eq!(
+            mark(":::note\n:::warning\nHello\n"),
+            "<!-- admonition note Note -->\n<!-- admonition warning Warning -->\nHello\n<!-- /admonition -->\n\
+             <!-- /admonition -->\n"
+        );
+    }
+
+    #[test]
+    fn mark_leaves_other_colons_alone() {
+        for source in [
+            ":::\n",
+            "Hello\n:::\n",
+            ":::tip\nHello\n:::\n",
+            ":: note\n",
+            "```\n:::note\n```\n",
+            "---\ntitle = \":::note\"\n---\n",
+        ] {
+            assert_eq!(mark(source), source);
+        }
+    }
+
+    #[test]
+    fn to_html_renders_markers() {
+        assert_eq!(
+            to_html("<!-- admonition warning <Careful> -->\n").as_deref(),
+            Some(
+                "<aside class=\"admonition admonition-warning\">\n\
+                 <p class=\"admonition-title\">&lt;Careful&gt;</p>\n"
+            )
+        );
+
+        assert_eq!(
+            to_html("<!-- /admonition -->\n").as_deref(),
+            Some("</aside>\n")
+        );
+    }
+
+    #[test]
+    fn to_html_ignores_other_html() {
+        for html in [
+            "<!-- a comment -->",
+            "<!-- admonition tip Tip -->",
+            "<!-- admonition note -->",
+            "<aside></aside>",
+        ] {
+            assert_eq!(to_html(html), None);
+        }
+    }
+}
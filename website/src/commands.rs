@@ -0,0 +1,211 @@
+use axum::Server;
+use chrono::Local;
+use std::{fs, net::SocketAddr, path::PathBuf};
+use tracing::{event, field::Empty, span, Instrument, Level};
+
+use crate::{
+    error::{Error, Result},
+    export,
+    markdown::{self, Fallback},
+    page::Page,
+    posts::Posts,
+    router, schedule,
+    shutdown::Shutdown,
+    state::{Config, State},
+    templates::Engine,
+    themes::Themes,
+    watcher,
+};
+
+/// Run the web server until it's asked to shut down.
+pub async fn serve(config: Config) -> Result<()> {
+    let address = config.host().address();
+    let port = config.host().port();
+
+    let (shutdown, signal) = Shutdown::new();
+    let state = State::try_new(config)?;
+
+    // The watcher stops as soon as it's dropped, so it needs to stick around until the server is closed.
+    let _watcher = watcher::watch(state.clone())?;
+
+    event!(Level::INFO, "Watching content directory for changes");
+
+    tokio::spawn(schedule::publish_scheduled(state.clone()));
+
+    let router = router(state, shutdown);
+
+    event!(Level::INFO, "Starting server...");
+
+    Server::bind(&SocketAddr::new(address, port))
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(signal)
+        .instrument(span!(Level::INFO, "server"))
+        .await?;
+
+    event!(Level::INFO, error = Empty, "server closed gracefully");
+
+    Ok(())
+}
+
+/// Render the entire site into `out`.
+pub async fn build(config: Config, out: PathBuf) -> Result<()> {
+    // Nothing is going to ask us to shut down, but the router still expects a `Shutdown` to be available.
+    let (shutdown, _signal) = Shutdown::new();
+    let state = State::try_new(config)?;
+    let router = router(state.clone(), shutdown);
+
+    export::build(router, &state, &out).await?;
+
+    event!(Level::INFO, out = %out.display(), "Finished building site");
+
+    Ok(())
+}
+
+/// Import every post, page and template, returning an error if any of them fail.
+///
+/// The individual failures are logged as they're encountered. Code blocks that couldn't be highlighted are listed at
+/// the end, but don't count as failures.
+pub fn check(config: Config) -> Result<()> {
+    let content_dir = config.content_dir();
+
+    let mut posts = Posts::new();
+    let report = posts.refresh(&config)?;
+    let mut error_count = report.failed().len();
+
+    let mut fallbacks: Vec<(String, Fallback)> = report
+        .fallbacks()
+        .iter()
+        .flat_map(|(slug, fallbacks)| {
+            fallbacks
+                .iter()
+                .map(move |fallback| (slug.clone(), fallback.clone()))
+        })
+        .collect();
+
+    for entry in fs::read_dir(content_dir.join("pages"))? {
+        let path = entry?.path();
+
+        match Page::simple(&path) {
+            Ok(page) => {
+                let diagnostics = page.diagnostics();
+
+                for warning in diagnostics.warnings() {
+                    event!(Level::WARN, path = %path.display(), warning, "Warning while importing page");
+                }
+
+                fallbacks.extend(
+                    diagnostics
+                        .fallbacks()
+                        .iter()
+                        .map(|fallback| (path.display().to_string(), fallback.clone())),
+                );
+            }
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    path = %path.display(),
+                    error = &error as &dyn std::error::Error,
+                    "Error importing page"
+                );
+
+                error_count += 1;
+            }
+        }
+    }
+
+    if let Err(error) = Engine::load(&content_dir.join("templates"), config.site().context()) {
+        event!(
+            Level::ERROR,
+            error = &error as &dyn std::error::Error,
+            "Error loading templates"
+        );
+
+        error_count += 1;
+    }
+
+    if let Err(error) = Themes::load(&content_dir.join("themes"), config.highlight()) {
+        event!(
+            Level::ERROR,
+            error = &error as &dyn std::error::Error,
+            "Error loading highlighting themes"
+        );
+
+        error_count += 1;
+    }
+
+    for (source, fallback) in &fallbacks {
+        event!(
+            Level::WARN,
+            source,
+            line = fallback.line(),
+            language = fallback.language(),
+            "Code block fell back to plain text"
+        );
+    }
+
+    if !fallbacks.is_empty() {
+        event!(
+            Level::WARN,
+            "{} code blocks fell back to plain text, as their language couldn't be found. Consider adding an alias",
+            fallbacks.len()
+        );
+    }
+
+    match error_count {
+        0 => {
+            event!(Level::INFO, "Everything looks good!");
+            Ok(())
+        }
+        _ => Err(Error::msg(format!("found {error_count} errors"))),
+    }
+}
+
+/// Create a new, empty blog post with the given title.
+pub fn new_post(config: Config, title: &str) -> Result<()> {
+    let slug = markdown::slugify(title, '_');
+
+    if slug.is_empty() {
+        return Err(Error::msg(
+            "the title must contain at least one letter or number",
+        ));
+    }
+
+    let path = config
+        .content_dir()
+        .join("blog-pages")
+        .join(format!("{slug}.md"));
+
+    if path.exists() {
+        return Err(Error::msg(format!("{} already exists", path.display())));
+    }
+
+    // Going through `toml` takes care of quoting and escaping the title for us.
+    let title = toml::Value::String(title.to_owned());
+    let published = Local::now().format("%Y-%m-%dT%H:%M:%S");
+
+    fs::write(
+        &path,
+        format!("---\ntitle = {title}\npublished = {published}\n---\n\n"),
+    )?;
+
+    event!(Level::INFO, path = %path.display(), "Created new post");
+
+    Ok(())
+}
+
+/// Print the resolved configuration as TOML.
+///
+/// The webhook secret is redacted, since this tends to end up in terminals and logs.
+pub fn config(config: Config) -> Result<()> {
+    let mut config =
+        toml::Value::try_from(&config).map_err(|error| Error::msg(error.to_string()))?;
+
+    if let Some(secret) = config.get_mut("webhook_secret") {
+        *secret = toml::Value::String("<redacted>".to_owned());
+    }
+
+    let config = toml::to_string_pretty(&config).map_err(|error| Error::msg(error.to_string()))?;
+    println!("{config}");
+
+    Ok(())
+}
@@ -7,6 +7,7 @@ use axum::{
 };
 use figment::Error as FigmentError;
 use hyper::{Error as HyperError, StatusCode};
+use notify::Error as NotifyError;
 use serde_json::Error as JsonError;
 use std::{borrow::Cow, io::Error as IoError};
 use syntect::LoadingError;
@@ -111,12 +112,21 @@ pub enum Error {
     Hyper(#[from] HyperError),
     #[error("{0}")]
     Syntax(#[from] LoadingError),
+    /// Figment's errors are much larger than everything else, so they're boxed to keep `Error` small.
     #[error("{0}")]
-    Config(#[from] FigmentError),
+    Config(Box<FigmentError>),
+    #[error("{0}")]
+    Watch(#[from] NotifyError),
     #[error("{0}")]
     Custom(Cow<'static, str>),
 }
 
+impl From<FigmentError> for Error {
+    fn from(error: FigmentError) -> Self {
+        Self::Config(Box::new(error))
+    }
+}
+
 impl Error {
     /// Create a new `Error` with a string message.
     pub fn msg(message: impl Into<Cow<'static, str>>) -> Self {
@@ -182,8 +192,11 @@ where
         "hide_navbar" => true,
     );
 
+    let status = response.status();
+
     Page::new("error", context)
-        .render(state.engine())
+        .render(&state.engine())
+        .map(|page| (status, page))
         .into_http_result()
         .into_response()
 }
@@ -0,0 +1,171 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use std::{
+    fs,
+    io::Result as IoResult,
+    path::{Path, PathBuf},
+};
+use tower::ServiceExt;
+use tracing::{event, Level};
+
+use crate::{
+    error::{Error, Result},
+    images, posts, routes,
+    state::State,
+};
+
+/// A path that no route will ever match, used to render the error page.
+const NOT_FOUND_ROUTE: &str = "/this-page-does-not-exist";
+
+/// Render every page on the site into `out`, alongside a copy of the static content directory.
+///
+/// Pages are rendered by sending requests through `router`, so the output is exactly what the server would respond
+/// with.
+pub async fn build(router: Router, state: &State, out: &Path) -> Result<()> {
+    copy_dir(&state.config().content_dir().join("static"), out)?;
+
+    // Assets are served from the same place as the post that they're bundled with, but the post itself isn't.
+    for (slug, directory) in state.posts().bundles() {
+        let destination = out.join("blog").join("post").join(slug);
+        copy_dir(directory, &destination)?;
+        fs::remove_file(destination.join(posts::BUNDLE_INDEX))?;
+    }
+
+    for route in routes(state) {
+        let body = fetch(&router, &route, StatusCode::OK).await?;
+        write(&output_path(out, &route), &body)?;
+    }
+
+    // Theme names often contain spaces and the like, which would need escaping to be requested through the router.
+    // Stylesheets are served exactly as they're stored, so they can be written out directly instead.
+    for (name, css) in state.themes().iter() {
+        write(
+            &out.join("highlight").join(format!("{name}.css")),
+            css.as_bytes(),
+        )?;
+    }
+
+    // Images are resized when posts are imported, which has already happened by the time `state` is loaded.
+    let image_cache_dir = state.config().image_cache_dir();
+
+    if image_cache_dir.is_dir() {
+        copy_dir(
+            &image_cache_dir,
+            &out.join(images::ROUTE.trim_start_matches('/')),
+        )?;
+    }
+
+    let body = fetch(&router, NOT_FOUND_ROUTE, StatusCode::NOT_FOUND).await?;
+    write(&out.join("404.html"), &body)?;
+
+    Ok(())
+}
+
+/// Return every route that can be rendered ahead of time.
+fn routes(state: &State) -> Vec<String> {
+    let mut routes: Vec<String> = [
+        "/",
+        "/about",
+        "/sitemap.xml",
+        "/robots.txt",
+        "/highlight.css",
+        "/blog",
+        "/blog/feed.rss",
+        "/blog/feed.atom",
+        "/blog/feed.json",
+        "/blog/tags",
+    ]
+    .into_iter()
+    .map(str::to_owned)
+    .collect();
+
+    let posts = state.posts();
+    let page_count = posts.page_count(state.config().posts_per_page());
+    routes.extend((2..=page_count).map(routes::page_url));
+    routes.extend(posts.iter().map(|(slug, _)| format!("/blog/post/{slug}")));
+    routes.extend(posts.tags().map(|(slug, _)| format!("/blog/tag/{slug}")));
+    routes.extend(
+        posts
+            .series()
+            .map(|(slug, _)| format!("/blog/series/{slug}")),
+    );
+
+    for (year, months) in posts.archive() {
+        routes.push(format!("/blog/{year}"));
+        routes.extend(
+            months
+                .keys()
+                .map(|month| format!("/blog/{year}/{month:02}")),
+        );
+    }
+
+    routes
+}
+
+/// Request `route` from `router`, and return the response body if the response has the `expected` status code.
+async fn fetch(router: &Router, route: &str, expected: StatusCode) -> Result<Vec<u8>> {
+    let request = Request::get(route)
+        .body(Body::empty())
+        .map_err(|error| Error::msg(format!("{route} is not a valid route: {error}")))?;
+
+    // Routers never fail, errors are always turned into responses.
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|error| Error::msg(error.to_string()))?;
+
+    if status != expected {
+        return Err(Error::msg(format!(
+            "{route} responded with status code {status}, expected {expected}"
+        )));
+    }
+
+    Ok(body.to_vec())
+}
+
+/// Map a route to the file it should be written to.
+///
+/// Routes that look like files (`/blog/feed.rss`) are written as-is, while everything else is written to an
+/// `index.html` file so that the route resolves on a plain file server.
+fn output_path(out: &Path, route: &str) -> PathBuf {
+    let relative = Path::new(route.trim_start_matches('/'));
+
+    match relative.extension() {
+        Some(_) => out.join(relative),
+        None => out.join(relative).join("index.html"),
+    }
+}
+
+fn write(path: &Path, contents: &[u8]) -> IoResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, contents)?;
+    event!(Level::INFO, path = %path.display(), "Wrote file");
+
+    Ok(())
+}
+
+/// Recursively copy the contents of `from` into `to`.
+fn copy_dir(from: &Path, to: &Path) -> IoResult<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let destination = to.join(entry.file_name());
+
+        match entry.file_type()?.is_dir() {
+            true => copy_dir(&entry.path(), &destination)?,
+            false => {
+                fs::copy(entry.path(), destination)?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use atom_syndication::{
+    CategoryBuilder, ContentBuilder, EntryBuilder, Feed, FeedBuilder, FixedDateTime, LinkBuilder,
+    PersonBuilder, Text,
+};
+use chrono::{DateTime, Local};
+use indexmap::IndexMap;
+use rss::{Channel, ChannelBuilder, GuidBuilder, ImageBuilder, ItemBuilder};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    markdown,
+    page::Page,
+    state::{FeedConfig, SiteConfig},
+};
+
+/// A JSON Feed, as described by <https://www.jsonfeed.org/version/1.1/>.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonFeed {
+    version: &'static str,
+    title: String,
+    home_page_url: String,
+    feed_url: String,
+    description: String,
+    icon: String,
+    authors: Vec<JsonFeedAuthor>,
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct JsonFeedAuthor {
+    name: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct JsonFeedItem {
+    id: String,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    content_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_modified: Option<String>,
+    tags: Vec<String>,
+}
+
+/// Return the canonical URL of the post `slug`.
+///
+/// This is used as the ID of the post in every feed, so that feed readers can match them up.
+fn post_url(site: &SiteConfig, slug: &str) -> String {
+    site.url(&format!("/blog/post/{slug}"))
+}
+
+/// Return when `page` was last updated, falling back to when it was published.
+fn updated(page: &Page) -> Option<FixedDateTime> {
+    page.updated().or_else(|| page.published())
+}
+
+/// Return the full content of the post `slug`, if `options` asks for it.
+///
+/// Feed readers don't know where an entry came from, so relative URLs are made absolute.
+fn full_content(
+    site: &SiteConfig,
+    slug: &str,
+    page: &Page,
+    options: &FeedConfig,
+) -> Option<String> {
+    if !options.full_content() {
+        return None;
+    }
+
+    let base = Url::parse(&post_url(site, slug)).ok()?;
+
+    page.content()
+        .map(|content| absolutize_urls(&content, &base))
+}
+
+/// Rewrite every relative URL in the attributes of `html` so that it's resolved against `base`.
+fn absolutize_urls(html: &str, base: &Url) -> String {
+    markdown::rewrite_urls(html, |value| match Url::parse(value) {
+        Err(url::ParseError::RelativeUrlWithoutBase) => base.join(value).ok().map(String::from),
+        _ => None,
+    })
+}
+
+/// Build an RSS channel from `pages`, which are expected to be in sorted order.
+pub fn rss_channel(
+    pages: &IndexMap<String, Page>,
+    site: &SiteConfig,
+    options: &FeedConfig,
+) -> Channel {
+    let publish_date = |(_, page): (_, &Page)| page.published().as_ref().map(DateTime::to_rfc2822);
+
+    let build_item = |(slug, page): (&String, &Page)| {
+        ItemBuilder::default()
+            .author(Some(site.author().email().to_owned()))
+            .link(Some(post_url(site, slug)))
+            .title(page.title())
+            .guid(Some(
+                GuidBuilder::default()
+                    .value(post_url(site, slug))
+                    .permalink(true)
+                    .build(),
+            ))
+            .description(page.description())
+            .content(full_content(site, slug, page, options))
+            .pub_date(page.published().map(|date| date.to_rfc2822()))
+            .build()
+    };
+
+    let image = ImageBuilder::default()
+        .url(site.logo())
+        .link(site.url("/blog"))
+        .title(site.title().to_owned())
+        .build();
+
+    let contact = format!("{} ({})", site.author().email(), site.author().name());
+
+    let channel = ChannelBuilder::default()
+        .title(site.title().to_owned())
+        .link(site.url("/blog"))
+        .description(site.description().to_owned())
+        .webmaster(Some(contact.clone()))
+        .managing_editor(Some(contact))
+        .last_build_date(pages.first().and_then(publish_date))
+        .pub_date(pages.last().and_then(publish_date))
+        .copyright(Some(site.copyright().to_owned()))
+        .image(Some(image))
+        .items(pages.iter().map(build_item).collect::<Vec<_>>())
+        .build();
+
+    channel
+}
+
+/// Build an Atom feed from `pages`, which are expected to be in sorted order.
+pub fn atom_feed(pages: &IndexMap<String, Page>, site: &SiteConfig, options: &FeedConfig) -> Feed {
+    let author = PersonBuilder::default()
+        .name(site.author().name())
+        .email(Some(site.author().email().to_owned()))
+        .uri(Some(site.base_url().to_string()))
+        .build();
+
+    let build_entry = |(slug, page): (&String, &Page)| {
+        let url = post_url(site, slug);
+        let categories: Vec<_> = page
+            .tags()
+            .iter()
+            .map(|tag| {
+                CategoryBuilder::default()
+                    .term(tag.slug())
+                    .label(Some(tag.name().to_owned()))
+                    .build()
+            })
+            .collect();
+
+        let content = full_content(site, slug, page, options).map(|content| {
+            ContentBuilder::default()
+                .value(Some(content))
+                .content_type(Some("html".to_owned()))
+                .build()
+        });
+
+        EntryBuilder::default()
+            .id(url.clone())
+            .title(Text::plain(page.title().unwrap_or_default()))
+            .links(vec![LinkBuilder::default()
+                .href(url)
+                .rel("alternate")
+                .build()])
+            .updated(updated(page).unwrap_or_default())
+            .published(page.published())
+            .summary(page.description().map(Text::plain))
+            .content(content)
+            .authors(vec![author.clone()])
+            .categories(categories)
+            .build()
+    };
+
+    let links = vec![
+        LinkBuilder::default()
+            .href(site.url("/blog"))
+            .rel("alternate")
+            .build(),
+        LinkBuilder::default()
+            .href(site.url("/blog/feed.atom"))
+            .rel("self")
+            .mime_type(Some("application/atom+xml".to_owned()))
+            .build(),
+    ];
+
+    let entries: Vec<_> = pages.iter().map(build_entry).collect();
+
+    // The feed as a whole was last updated whenever the most recently updated entry was.
+    let last_updated = entries
+        .iter()
+        .map(|entry| *entry.updated())
+        .max()
+        .unwrap_or_else(|| Local::now().into());
+
+    FeedBuilder::default()
+        .id(site.url("/blog"))
+        .title(Text::plain(site.title()))
+        .subtitle(Some(Text::plain(site.description())))
+        .links(links)
+        .authors(vec![author])
+        .updated(last_updated)
+        .icon(Some(site.logo()))
+        .rights(Some(Text::plain(site.copyright())))
+        .entries(entries)
+        .build()
+}
+
+/// Build a JSON Feed from `pages`, which are expected to be in sorted order.
+pub fn json_feed(
+    pages: &IndexMap<String, Page>,
+    site: &SiteConfig,
+    options: &FeedConfig,
+) -> JsonFeed {
+    let build_item = |(slug, page): (&String, &Page)| {
+        let description = page.description();
+
+        JsonFeedItem {
+            id: post_url(site, slug),
+            url: post_url(site, slug),
+            title: page.title(),
+            content_text: description.clone().unwrap_or_default(),
+            content_html: full_content(site, slug, page, options),
+            summary: description,
+            date_published: page.published().as_ref().map(DateTime::to_rfc3339),
+            date_modified: updated(page).as_ref().map(DateTime::to_rfc3339),
+            tags: page
+                .tags()
+                .iter()
+                .map(|tag| tag.name().to_owned())
+                .collect(),
+        }
+    };
+
+    JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: site.title().to_owned(),
+        home_page_url: site.url("/blog"),
+        feed_url: site.url("/blog/feed.json"),
+        description: site.description().to_owned(),
+        icon: site.logo(),
+        authors: vec![JsonFeedAuthor {
+            name: site.author().name().to_owned(),
+            url: site.base_url().to_string(),
+        }],
+        items: pages.iter().map(build_item).collect(),
+    }
+}
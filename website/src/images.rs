@@ -0,0 +1,265 @@
+use hex::ToHex;
+use image::{
+    codecs::avif::AvifEncoder, imageops::FilterType, DynamicImage, ExtendedColorType, ImageEncoder,
+    ImageFormat as SourceFormat, ImageReader,
+};
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+use url::Url;
+
+use crate::{
+    markdown,
+    state::{Config, ImageFormat, ImagesConfig},
+    IMAGES,
+};
+
+/// The route that resized images are served from.
+pub const ROUTE: &str = "/images";
+
+/// Formats that can be resized without losing anything. Animated GIFs would lose their animation, for example.
+const RESIZABLE_FORMATS: [SourceFormat; 3] =
+    [SourceFormat::Png, SourceFormat::Jpeg, SourceFormat::WebP];
+
+/// How much effort goes into encoding AVIF images, from 1 (slowest) to 10 (fastest).
+///
+/// Anything much slower than this makes importing posts with lots of new images painful, for not a lot of gain.
+const AVIF_SPEED: u8 = 8;
+
+/// Where images are read from and written to, along with how they're resized.
+#[derive(Debug, Default)]
+pub struct Images {
+    static_dir: PathBuf,
+    cache_dir: PathBuf,
+    config: ImagesConfig,
+}
+
+impl Images {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            static_dir: config.content_dir().join("static"),
+            cache_dir: config.image_cache_dir(),
+            config: config.images().clone(),
+        }
+    }
+
+    /// Render the image at `url` as HTML, generating resized versions of it if they don't exist yet.
+    ///
+    /// Relative URLs are resolved against `page_url`, the URL of the page that the image is on. Images are looked up in
+    /// the static content directory, or in `bundle` (the directory that's served from `page_url`) if they're part of
+    /// it. Images that aren't on this site can't be resized, so they're only loaded lazily.
+    ///
+    /// This function returns a description of the problem if the image doesn't exist, or can't be resized.
+    pub fn render(
+        &self,
+        url: &str,
+        alt: &str,
+        title: &str,
+        page_url: &str,
+        bundle: Option<&Path>,
+    ) -> Result<String, String> {
+        let mut img = format!(
+            "<img src=\"{}\" alt=\"{}\"",
+            markdown::escape_url(url),
+            tera::escape_html(alt)
+        );
+
+        if !title.is_empty() {
+            img.push_str(&format!(" title=\"{}\"", tera::escape_html(title)));
+        }
+
+        let Some(path) = self.resolve(url, page_url, bundle)? else {
+            return Ok(format!("{img} loading=\"lazy\" decoding=\"async\" />"));
+        };
+
+        if !path.is_file() {
+            return Err(format!("`{url}` doesn't exist"));
+        }
+
+        let bytes = fs::read(&path).map_err(|error| format!("unable to read `{url}`: {error}"))?;
+
+        // Some images (like SVGs) can't be read at all, but they can still be lazily loaded.
+        let reader = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .ok()
+            .filter(|reader| reader.format().is_some());
+
+        let format = reader.as_ref().and_then(ImageReader::format);
+        let dimensions = reader.and_then(|reader| reader.into_dimensions().ok());
+
+        let sources = match (format, dimensions) {
+            (Some(format), Some((width, _))) if RESIZABLE_FORMATS.contains(&format) => self
+                .resize(&path, &bytes, width)
+                .map_err(|error| format!("unable to resize `{url}`: {error}"))?,
+            _ => Vec::new(),
+        };
+
+        if let Some((width, height)) = dimensions {
+            img.push_str(&format!(" width=\"{width}\" height=\"{height}\""));
+        }
+
+        img.push_str(" loading=\"lazy\" decoding=\"async\" />");
+
+        if sources.is_empty() {
+            return Ok(img);
+        }
+
+        let sources: String = sources
+            .into_iter()
+            .map(|(format, srcset)| {
+                format!(
+                    "<source type=\"{}\" srcset=\"{srcset}\" sizes=\"{}\" />",
+                    format.mime_type(),
+                    tera::escape_html(self.config.sizes())
+                )
+            })
+            .collect();
+
+        Ok(format!("<picture>{sources}{img}</picture>"))
+    }
+
+    /// Find the file that `url` refers to, returning `None` if it isn't on this site.
+    fn resolve(
+        &self,
+        url: &str,
+        page_url: &str,
+        bundle: Option<&Path>,
+    ) -> Result<Option<PathBuf>, String> {
+        if url.is_empty() || url.starts_with("//") || Url::parse(url).is_ok() {
+            return Ok(None);
+        }
+
+        // The `url` crate can only resolve URLs against another absolute URL, so any host will do.
+        let resolved = Url::parse("http://localhost")
+            .and_then(|origin| origin.join(page_url))
+            .and_then(|page| page.join(url))
+            .map_err(|_| format!("`{url}` isn't a valid URL"))?;
+
+        let path = percent_decode_str(resolved.path()).decode_utf8_lossy();
+
+        let (directory, relative) = bundle
+            .and_then(|directory| Some((directory, path.strip_prefix(page_url)?)))
+            .unwrap_or((self.static_dir.as_path(), path.trim_start_matches('/')));
+
+        let relative = Path::new(relative);
+
+        // Anything other than plain file names (like `..`) could escape the directory.
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!("`{url}` isn't a valid path"));
+        }
+
+        Ok(Some(directory.join(relative)))
+    }
+
+    /// Resize the image at `path`, returning a `srcset` for each configured format.
+    ///
+    /// Resized images are named after the original and a hash of its contents, so they only need to be generated once.
+    /// The original width is always included, so that browsers can pick a better format even if they need every pixel.
+    fn resize(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        width: u32,
+    ) -> Result<Vec<(ImageFormat, String)>, String> {
+        let stem = path
+            .file_stem()
+            .map(|stem| markdown::slugify(&stem.to_string_lossy(), '-'))
+            .unwrap_or_default();
+
+        let hash: String = Sha256::digest(bytes).encode_hex();
+        let name = format!("{stem}-{}", &hash[..16]);
+
+        let mut widths: Vec<u32> = self
+            .config
+            .widths()
+            .iter()
+            .copied()
+            .filter(|&resized| resized > 0 && resized < width)
+            .chain([width])
+            .collect();
+
+        widths.sort_unstable();
+        widths.dedup();
+
+        let mut image = None;
+        let mut sources = Vec::new();
+
+        for &format in self.config.formats() {
+            let mut srcset = Vec::new();
+
+            for &resized in &widths {
+                let file_name = format!("{name}-{resized}.{}", format.extension());
+                let file = self.cache_dir.join(&file_name);
+
+                if !file.is_file() {
+                    let image = match image {
+                        Some(ref image) => image,
+                        None => image.insert(
+                            image::load_from_memory(bytes).map_err(|error| error.to_string())?,
+                        ),
+                    };
+
+                    let encoded = encode(
+                        &image.resize(resized, u32::MAX, FilterType::Lanczos3),
+                        format,
+                        self.config.quality(),
+                    )?;
+
+                    write(&file, &encoded).map_err(|error| {
+                        format!("unable to write `{}`: {error}", file.display())
+                    })?;
+                }
+
+                srcset.push(format!("{ROUTE}/{file_name} {resized}w"));
+            }
+
+            sources.push((format, srcset.join(", ")));
+        }
+
+        Ok(sources)
+    }
+}
+
+/// Replace the image settings currently in use.
+pub fn configure(images: Images) {
+    *IMAGES.write().unwrap() = images;
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>, String> {
+    let rgba = image.to_rgba8();
+
+    match format {
+        ImageFormat::Webp => Ok(webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+            .encode(f32::from(quality))
+            .to_vec()),
+        ImageFormat::Avif => {
+            let mut buffer = Vec::new();
+
+            AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, quality)
+                .write_image(&rgba, rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+                .map_err(|error| error.to_string())?;
+
+            Ok(buffer)
+        }
+    }
+}
+
+/// Write `contents` to `path` all at once, so that a half-written image is never served.
+fn write(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // The extension is kept, so that different formats of the same image don't share a temporary file.
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, path)
+}
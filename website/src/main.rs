@@ -1,36 +1,85 @@
 use axum::{
     body::Body,
     routing::{get, post},
-    Extension, Router, Server,
+    Extension, Router,
 };
 use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use error::Error;
+use images::Images;
 use lazy_static::lazy_static;
+use shortcodes::Shortcodes;
 use shutdown::Shutdown;
-use state::{Config, State};
+use state::{Config, MarkdownConfig, State};
 use std::{
-    net::SocketAddr,
+    path::PathBuf,
     process::ExitCode,
     sync::{Arc, RwLock},
     time::SystemTime,
 };
-use syntect::parsing::{SyntaxSet, SyntaxSetBuilder};
+use syntect::parsing::SyntaxSet;
 use tower::ServiceBuilder;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
-use tracing::{event, field::Empty, span, Instrument, Level};
+use tracing::{event, Level};
 use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
 
+mod admonitions;
+mod commands;
 mod error;
+mod export;
+mod feeds;
+mod images;
 mod markdown;
+mod math;
 mod page;
 mod posts;
 mod routes;
+mod schedule;
+mod shortcodes;
 mod shutdown;
+mod sitemap;
 mod state;
 mod templates;
+mod themes;
+mod watcher;
 
 lazy_static! {
     pub static ref SYNTAX_SET: Arc<RwLock<SyntaxSet>> = Default::default();
+    pub static ref MARKDOWN_CONFIG: Arc<RwLock<MarkdownConfig>> = Default::default();
+    pub static ref SHORTCODES: Arc<RwLock<Shortcodes>> = Default::default();
+    pub static ref IMAGES: Arc<RwLock<Images>> = Default::default();
+}
+
+/// The backend for kaylynn.gay
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// The configuration profile to use. Defaults to "debug" for debug builds, and "release" for release builds.
+    #[arg(long, global = true)]
+    profile: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the web server. This is the default if no command is given.
+    Serve,
+    /// Render the entire site to a directory of static files.
+    Build {
+        /// The directory to write the rendered site to.
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Check that every post and page can be imported, exiting with an error if any of them can't.
+    Check,
+    /// Create a new blog post with the given title.
+    NewPost {
+        /// The title of the post. The file name is derived from this.
+        title: String,
+    },
+    /// Print the configuration that would be used for the selected profile.
+    Config,
 }
 
 struct Timer;
@@ -44,57 +93,76 @@ impl FormatTime for Timer {
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    match run().await {
-        Ok(()) => {
-            event!(Level::INFO, error = Empty, "server closed gracefully");
-            ExitCode::SUCCESS
-        }
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
         Err(error) => {
-            event!(Level::ERROR, %error, "server closed with error");
+            event!(Level::ERROR, %error, "exited with error");
             ExitCode::FAILURE
         }
     }
 }
 
-async fn run() -> Result<(), Error> {
+async fn run(cli: Cli) -> Result<(), Error> {
     tracing_subscriber::fmt()
         .with_target(false)
         .with_ansi(true)
         .compact()
         .with_timer(Timer)
+        .with_writer(std::io::stderr)
         .init();
 
-    let profile = match cfg!(debug_assertions) {
-        true => "debug",
-        false => "release",
-    };
+    let profile = cli.profile.unwrap_or_else(|| {
+        match cfg!(debug_assertions) {
+            true => "debug",
+            false => "release",
+        }
+        .to_owned()
+    });
 
     event!(Level::INFO, "Running in \"{}\" mode", profile);
 
-    let config = Config::figment().select(profile).extract::<Config>()?;
-    let address = config.host().address();
-    let port = config.host().port();
+    let config = Config::figment().select(&profile).extract::<Config>()?;
 
     event!(
         Level::INFO,
         config.webhook_secret = config.webhook_secret(),
         config.content_dir = %config.content_dir().display(),
-        config.host.address = %address,
-        config.host.port = port,
+        config.host.address = %config.host().address(),
+        config.host.port = config.host().port(),
         "Loaded configuration from environment"
     );
 
-    // This is a really, really evil hack. But doing it this way prevents us from passing it down the call stack when
-    // parsing/rendering markdown, which is a lot nicer.
-    let mut builder = SyntaxSetBuilder::new();
-    builder.add_from_folder(&config.content_dir().join("syntaxes"), true)?;
-    *SYNTAX_SET.write().unwrap() = builder.build();
+    let command = cli.command.unwrap_or(Command::Serve);
 
-    event!(Level::INFO, "Loaded highlighting syntaxes");
+    // Syntaxes take a little while to load, so there's no point in loading them for commands that don't need them.
+    if matches!(
+        command,
+        Command::Serve | Command::Build { .. } | Command::Check
+    ) {
+        // This is a really, really evil hack. But doing it this way prevents us from passing it down the call stack
+        // when parsing/rendering markdown, which is a lot nicer.
+        markdown::load_syntaxes(&config.content_dir().join("syntaxes"))?;
+        markdown::configure(config.markdown().clone());
+        images::configure(Images::new(&config));
 
-    let (shutdown, signal) = Shutdown::new();
-    let state = State::try_new(config)?;
+        event!(Level::INFO, "Loaded highlighting syntaxes");
 
+        markdown::load_shortcodes(&config.content_dir().join("templates").join("shortcodes"))?;
+    }
+
+    match command {
+        Command::Serve => commands::serve(config).await,
+        Command::Build { out } => commands::build(config, out).await,
+        Command::Check => commands::check(config),
+        Command::NewPost { title } => commands::new_post(config, &title),
+        Command::Config => commands::config(config),
+    }
+}
+
+/// Build the router used to serve every page on the site.
+pub fn router(state: State, shutdown: Shutdown) -> Router {
     // This service is just responsible for logging incoming requests. It's not as bad as it looks!
     let trace_service = TraceLayer::new_for_http()
         .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -106,22 +174,27 @@ async fn run() -> Result<(), Error> {
         .layer(Extension(state))
         .layer(axum::middleware::from_fn(error::to_error_page));
 
-    let router = Router::<(), Body>::new()
+    Router::<(), Body>::new()
         .route("/", get(routes::simple("pages/home.md")))
         .route("/about", get(routes::simple("pages/about.md")))
+        .route("/sitemap.xml", get(routes::sitemap))
+        .route("/robots.txt", get(routes::robots))
+        .route("/highlight.css", get(routes::highlight_stylesheet))
+        .route("/highlight/:theme", get(routes::theme_stylesheet))
+        .route("/images/:file", get(routes::image))
         .route("/deploy", post(routes::deploy))
+        .route("/deploy/content", post(routes::deploy_content))
         .route("/blog", get(routes::post_list))
+        .route("/blog/page/:number", get(routes::paged_post_list))
         .route("/blog/feed.rss", get(routes::rss_feed))
+        .route("/blog/feed.atom", get(routes::atom_feed))
+        .route("/blog/feed.json", get(routes::json_feed))
+        .route("/blog/tags", get(routes::tag_list))
+        .route("/blog/tag/:tag", get(routes::tagged_post_list))
+        .route("/blog/series/:series", get(routes::series))
         .route("/blog/post/:slug", get(routes::post))
-        .layer(services);
-
-    event!(Level::INFO, "Starting server...");
-
-    Server::bind(&SocketAddr::new(address, port))
-        .serve(router.into_make_service())
-        .with_graceful_shutdown(signal)
-        .instrument(span!(Level::INFO, "server"))
-        .await?;
-
-    Ok(())
+        .route("/blog/post/:slug/*file", get(routes::post_asset))
+        .route("/blog/:year", get(routes::year_archive))
+        .route("/blog/:year/:month", get(routes::month_archive))
+        .layer(services)
 }
@@ -1,28 +1,112 @@
 use comrak::{
     arena_tree::NodeEdge,
-    nodes::{AstNode, NodeHtmlBlock, NodeValue},
+    nodes::{AstNode, NodeHeading, NodeHtmlBlock, NodeValue},
     Arena, ComrakExtensionOptions, ComrakOptions, ComrakRenderOptions,
 };
-use lazy_static::lazy_static;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{borrow::Cow, collections::HashSet, iter::Peekable, ops::RangeInclusive, path::Path};
 use syntect::{
     html::{ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSetBuilder,
     util::LinesWithEndings,
 };
+use url::{Position, Url};
 
-use crate::{error::Result, SYNTAX_SET};
+use crate::{
+    admonitions,
+    error::{Error, Result},
+    math,
+    shortcodes::{self, Shortcodes},
+    state::MarkdownConfig,
+    IMAGES, MARKDOWN_CONFIG, SHORTCODES, SYNTAX_SET,
+};
 
 pub type NodeArena<'a> = &'a Arena<AstNode<'a>>;
 pub type NodeRef<'a> = &'a AstNode<'a>;
 
-lazy_static! {
-    static ref COMRAK_OPTIONS: ComrakOptions = ComrakOptions {
+/// Attributes that may contain a relative URL in rendered HTML.
+const URL_ATTRIBUTES: [&str; 3] = ["href=\"", "src=\"", "srcset=\""];
+
+/// Languages that are meant to be shown as plain text, so there's no point in reporting that they weren't highlighted.
+const PLAIN_TEXT_LANGUAGES: [&str; 4] = ["text", "txt", "plain", "plaintext"];
+
+/// Things worth pointing out about a document, which weren't serious enough to stop it from being parsed.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    warnings: Vec<String>,
+    fallbacks: Vec<Fallback>,
+}
+
+impl Diagnostics {
+    /// Return a description of each problem with the document.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Return each code block that was shown as plain text, because its language couldn't be highlighted.
+    pub fn fallbacks(&self) -> &[Fallback] {
+        &self.fallbacks
+    }
+}
+
+/// A code block that was shown as plain text, because its language couldn't be highlighted.
+#[derive(Debug, Clone, Serialize)]
+pub struct Fallback {
+    line: u32,
+    language: String,
+}
+
+impl Fallback {
+    /// The line that the code block starts on.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The language of the code block, as it was written.
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+}
+
+/// Load highlighting syntaxes from `directory`, replacing the ones currently in use.
+///
+/// The previous syntaxes are left in place if loading fails.
+pub fn load_syntaxes(directory: &Path) -> Result<()> {
+    let mut builder = SyntaxSetBuilder::new();
+    builder.add_from_folder(directory, true)?;
+    *SYNTAX_SET.write().unwrap() = builder.build();
+
+    Ok(())
+}
+
+/// Load shortcode templates from `directory`, replacing the ones currently in use.
+///
+/// The previous shortcodes are left in place if loading fails.
+pub fn load_shortcodes(directory: &Path) -> Result<()> {
+    *SHORTCODES.write().unwrap() = Shortcodes::load(directory)?;
+
+    Ok(())
+}
+
+/// Replace the options used when rendering Markdown.
+pub fn configure(config: MarkdownConfig) {
+    *MARKDOWN_CONFIG.write().unwrap() = config;
+}
+
+/// Build the options passed to comrak, according to the current `MarkdownConfig`.
+fn comrak_options() -> ComrakOptions {
+    let config = MARKDOWN_CONFIG.read().unwrap();
+    let extensions = config.extensions();
+
+    ComrakOptions {
         extension: ComrakExtensionOptions {
-            strikethrough: true,
-            table: true,
-            autolink: true,
-            tasklist: true,
-            description_lists: true,
+            strikethrough: extensions.strikethrough(),
+            table: extensions.table(),
+            autolink: extensions.autolink(),
+            tasklist: extensions.tasklist(),
+            description_lists: extensions.description_lists(),
+            footnotes: extensions.footnotes(),
+            superscript: extensions.superscript(),
             front_matter_delimiter: Some("---".to_owned()),
             ..Default::default()
         },
@@ -31,7 +115,7 @@ lazy_static! {
             ..Default::default()
         },
         ..Default::default()
-    };
+    }
 }
 
 /// Render a Markdown AST as HTML.
@@ -41,24 +125,39 @@ lazy_static! {
 /// This function panics if the output contains invalid UTF-8.
 pub fn render<'a>(document: NodeRef<'a>) -> String {
     let mut buffer = Vec::new();
-    comrak::format_html(document, &COMRAK_OPTIONS, &mut buffer).expect("writing output failed");
+    comrak::format_html(document, &comrak_options(), &mut buffer).expect("writing output failed");
 
     String::from_utf8(buffer).expect("output contained invalid UTF-8")
 }
 
 /// Parse raw Markdown source into an AST.
 ///
-/// This function returns a tuple of (metadata, AST) when successful. The metadata is extracted from the document's
-/// front matter and deserialized into the type `M`. The front matter is assumed to be in TOML format.
+/// This function returns a tuple of (metadata, AST, diagnostics) when successful. The metadata is extracted from the
+/// document's front matter and deserialized into the type `M`. The front matter is assumed to be in TOML format.
 ///
-/// This function returns an error if deserializing into `M` fails.
-pub fn parse<'a, 'de, M>(arena: NodeArena<'a>, content: &str) -> Result<(M, NodeRef<'a>)>
+/// This function returns an error if deserializing into `M` fails, or if shortcodes, math or highlighting fail.
+pub fn parse<'a, M>(arena: NodeArena<'a>, content: &str) -> Result<(M, NodeRef<'a>, Diagnostics)>
 where
     M: DeserializeOwned,
 {
-    let document = comrak::parse_document(&arena, content, &COMRAK_OPTIONS);
+    let admonitions = MARKDOWN_CONFIG.read().unwrap().extensions().admonitions();
+
+    let (content, rendered_shortcodes) = shortcodes::mark(content)?;
+    let mut content = math::mark(&content);
+
+    if admonitions {
+        content = admonitions::mark(&content);
+    }
 
-    highlight(document);
+    let document = comrak::parse_document(arena, &content, &comrak_options());
+
+    render_math(document)?;
+    render_shortcodes(document, &rendered_shortcodes);
+
+    if admonitions {
+        render_admonitions(document);
+    }
+    let diagnostics = highlight(document);
 
     let front_matter = traverse(document)
         .find_map(|node| match node.data.borrow().value {
@@ -74,7 +173,253 @@ where
 
     let metadata: M = toml::from_str(&front_matter)?;
 
-    Ok((metadata, document))
+    Ok((metadata, document, diagnostics))
+}
+
+/// Split raw Markdown source into lines, pairing each one with whether it's ordinary text.
+///
+/// Front matter and fenced codeblocks (fences included) aren't ordinary text, so that passes over the source can leave
+/// them alone.
+pub fn source_lines(source: &str) -> impl Iterator<Item = (&str, bool)> {
+    let mut fence = None;
+    let mut in_front_matter = false;
+
+    source
+        .split_inclusive('\n')
+        .enumerate()
+        .map(move |(index, line)| {
+            let trimmed = line.trim();
+
+            let is_text = if index == 0 && trimmed == "---" {
+                in_front_matter = true;
+                false
+            } else if in_front_matter {
+                in_front_matter = trimmed != "---";
+                false
+            } else if let Some(open) = fence {
+                if closes_fence(open, trimmed) {
+                    fence = None;
+                }
+
+                false
+            } else if let Some(open) = opening_fence(trimmed) {
+                fence = Some(open);
+                false
+            } else {
+                true
+            };
+
+            (line, is_text)
+        })
+}
+
+/// Return the fence that opens a fenced codeblock on this (trimmed) line, if there is one.
+fn opening_fence(line: &str) -> Option<&str> {
+    let fence = match line.chars().next()? {
+        character @ ('`' | '~') => &line[..line.len() - line.trim_start_matches(character).len()],
+        _ => return None,
+    };
+
+    (fence.len() >= 3).then_some(fence)
+}
+
+/// Whether this (trimmed) line closes the fenced codeblock opened by `open`.
+fn closes_fence(open: &str, line: &str) -> bool {
+    opening_fence(line).is_some_and(|fence| fence.starts_with(open) && fence.len() == line.len())
+}
+
+/// Return the length of the code span at the start of `text`, up to and including the closing backticks.
+///
+/// If the code span is never closed, only the opening backticks are counted, since they're just text.
+pub fn code_span_length(text: &str) -> usize {
+    let run = backtick_run(text);
+    find_backtick_run(&text[run..], run).map_or(run, |end| run + end + run)
+}
+
+fn backtick_run(text: &str) -> usize {
+    text.len() - text.trim_start_matches('`').len()
+}
+
+/// Find a run of exactly `length` backticks in `text`, returning where it starts.
+fn find_backtick_run(text: &str, length: usize) -> Option<usize> {
+    let mut searched = 0;
+
+    loop {
+        let start = searched + text[searched..].find('`')?;
+        let run = backtick_run(&text[start..]);
+
+        if run == length {
+            return Some(start);
+        }
+
+        searched = start + run;
+    }
+}
+
+/// Resolve the relative URLs in the Markdown AST against the path `base`, in-place.
+///
+/// This is used for posts that come with their own assets, so that they can refer to them by name. Links, images and
+/// the attributes of raw HTML (including shortcodes) are rewritten. Paths that start with a `/`, fragments and absolute
+/// URLs are left alone.
+pub fn rebase_urls<'a>(root: &'a AstNode<'a>, base: &str) {
+    // The `url` crate can only resolve URLs against another absolute URL, so any host will do.
+    let Ok(base) = Url::parse("http://localhost").and_then(|origin| origin.join(base)) else {
+        return;
+    };
+
+    let rebase = |value: &str| {
+        let relative = !value.is_empty()
+            && !value.starts_with(['/', '#', '?'])
+            && matches!(
+                Url::parse(value),
+                Err(url::ParseError::RelativeUrlWithoutBase)
+            );
+
+        relative
+            .then(|| base.join(value).ok())
+            .flatten()
+            .map(|url| url[Position::BeforePath..].to_owned())
+    };
+
+    for node in traverse(root) {
+        match node.data.borrow_mut().value {
+            NodeValue::Link(ref mut link) | NodeValue::Image(ref mut link) => {
+                if let Some(url) = rebase(&String::from_utf8_lossy(&link.url)) {
+                    link.url = url.into_bytes();
+                }
+            }
+            NodeValue::HtmlBlock(ref mut block) => {
+                block.literal =
+                    rewrite_urls(&String::from_utf8_lossy(&block.literal), rebase).into_bytes();
+            }
+            NodeValue::HtmlInline(ref mut literal) => {
+                *literal = rewrite_urls(&String::from_utf8_lossy(literal), rebase).into_bytes();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rewrite the URLs in each `href`, `src` and `srcset` attribute of `html`, leaving a URL alone if `rewrite` returns
+/// `None`.
+///
+/// URLs are passed to `rewrite` with any character references decoded, since templates (like shortcodes) escape
+/// characters such as `/`, and the rewritten URL is escaped again afterwards. This relies on attributes being quoted the
+/// way comrak quotes them, which is good enough for rendered posts.
+pub fn rewrite_urls(html: &str, rewrite: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some((start, attribute)) = URL_ATTRIBUTES
+        .iter()
+        .filter_map(|&attribute| Some((rest.find(attribute)? + attribute.len(), attribute)))
+        .min()
+    {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find('"').unwrap_or(rest.len());
+        let value = &rest[..end];
+
+        // A `srcset` is a list of URLs, each followed by a description of the image.
+        let candidates = match attribute {
+            "srcset=\"" => value.split(',').collect(),
+            _ => vec![value],
+        };
+
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            if index > 0 {
+                output.push(',');
+            }
+
+            let url_start = candidate.len() - candidate.trim_start().len();
+            let url_end = candidate[url_start..]
+                .find(char::is_whitespace)
+                .map_or(candidate.len(), |end| url_start + end);
+            let url = &candidate[url_start..url_end];
+
+            output.push_str(&candidate[..url_start]);
+
+            match rewrite(&decode_entities(url)) {
+                Some(rewritten) => output.push_str(&escape_url(&rewritten)),
+                None => output.push_str(url),
+            }
+
+            output.push_str(&candidate[url_end..]);
+        }
+
+        rest = &rest[end..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Escape `url` for use in an attribute.
+///
+/// `tera::escape_html` escapes slashes too, which makes URLs unrecognisable to anything that rewrites them later.
+pub fn escape_url(url: &str) -> String {
+    url.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Decode the character references in `text`, like `&amp;` and `&#x2F;`.
+///
+/// Only numeric references and the named references that templates produce are decoded. Anything else is left as-is.
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest[1..].split_once(';').and_then(|(name, _)| {
+            let character = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let number = name.strip_prefix('#')?;
+                    let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+                        Some(hex) => (hex, 16),
+                        None => (number, 10),
+                    };
+
+                    // `from_str_radix` accepts a leading `+`, which isn't valid here.
+                    if !digits.chars().all(|digit| digit.is_digit(radix)) {
+                        return None;
+                    }
+
+                    char::from_u32(u32::from_str_radix(digits, radix).ok()?)?
+                }
+            };
+
+            Some((character, name.len() + 2))
+        });
+
+        match decoded {
+            Some((character, length)) => {
+                output.push(character);
+                rest = &rest[length..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    Cow::Owned(output)
 }
 
 /// Return an iterator over each child node in the provided Markdown AST.
@@ -89,75 +434,605 @@ pub fn traverse<'a>(root: &'a AstNode<'a>) -> impl Iterator<Item = &'a AstNode<'
 ///
 /// For each fenced codeblock in the AST, the codeblock is parsed and syntax highlighting is performed. Then the
 /// original AST node is replaced with an inline HTML node containing the highlighted output.
-pub fn highlight<'a>(root: &'a AstNode<'a>) {
+///
+/// Languages are looked up by file extension and then by name, after resolving any aliases from `MarkdownConfig`.
+/// Codeblocks with a language that can't be found are left as plain text, and recorded as a fallback.
+///
+/// The info string of a codeblock may be followed by extra attributes, written as a TOML inline table:
+///
+/// ````markdown
+/// ```rust {lines = true, highlight = "3-5,8", title = "src/main.rs", start = 10}
+/// ````
+///
+/// - `lines` adds line numbers, starting from `start` (or 1).
+/// - `highlight` marks lines as emphasized. Lines are counted from the start of the codeblock, regardless of `start`.
+/// - `title` adds a caption above the codeblock, like a file name.
+///
+/// Returns a warning for each attribute that couldn't be understood.
+pub fn highlight<'a>(root: &'a AstNode<'a>) -> Diagnostics {
     let syntax_set = SYNTAX_SET.read().unwrap();
+    let config = MARKDOWN_CONFIG.read().unwrap();
+    let mut diagnostics = Diagnostics::default();
+
+    let front_matter_lines = front_matter_lines(root);
 
     for node in traverse(root) {
         let mut data = node.data.borrow_mut();
+        let line = data.start_line + front_matter_lines;
 
         if let NodeValue::CodeBlock(ref codeblock) = data.value {
-            // Panic safety: I solemnly swear I will never include invalid UTF-8 inside of my website.
-            let language = std::str::from_utf8(&codeblock.info).unwrap();
-            let code = std::str::from_utf8(&codeblock.literal).unwrap();
-
-            let syntax_reference = syntax_set
-                .find_syntax_by_extension(language)
-                .or_else(|| syntax_set.find_syntax_by_name(language));
+            let info = String::from_utf8_lossy(&codeblock.info);
+            let code = String::from_utf8_lossy(&codeblock.literal);
 
-            let syntax_reference = match syntax_reference {
-                Some(reference) => reference,
-                None => continue,
+            let (language, attributes) = match info.split_once('{') {
+                Some((language, attributes)) => (language.trim(), Some(attributes)),
+                None => (info.trim(), None),
             };
 
-            let mut html_generator = ClassedHTMLGenerator::new_with_class_style(
-                syntax_reference,
-                &syntax_set,
-                ClassStyle::SpacedPrefixed { prefix: "hl-" },
-            );
+            let attributes = attributes.map(|source| {
+                CodeAttributes::parse(source, |message| {
+                    diagnostics
+                        .warnings
+                        .push(format!("code block on line {line}: {message}"))
+                })
+            });
 
-            for line in LinesWithEndings::from(code) {
-                let _ = html_generator.parse_html_for_line_which_includes_newline(line);
+            let resolved = config
+                .language_aliases()
+                .get(language)
+                .map_or(language, String::as_str);
+
+            let syntax_reference = syntax_set.find_syntax_by_token(resolved);
+
+            if syntax_reference.is_none()
+                && !language.is_empty()
+                && !PLAIN_TEXT_LANGUAGES.contains(&language)
+            {
+                diagnostics.fallbacks.push(Fallback {
+                    line,
+                    language: language.to_owned(),
+                });
             }
 
+            let rendered = match (syntax_reference, &attributes) {
+                (Some(syntax_reference), _) => {
+                    let mut html_generator = ClassedHTMLGenerator::new_with_class_style(
+                        syntax_reference,
+                        &syntax_set,
+                        ClassStyle::SpacedPrefixed { prefix: "hl-" },
+                    );
+
+                    for line in LinesWithEndings::from(&code) {
+                        let _ = html_generator.parse_html_for_line_which_includes_newline(line);
+                    }
+
+                    html_generator.finalize()
+                }
+                // Attributes still need to be applied, even if there's nothing to highlight.
+                (None, Some(_)) => tera::escape_html(&code),
+                (None, None) => continue,
+            };
+
             // What follows may be considered a crime
             let mut new_node = NodeHtmlBlock::default();
-            let rendered = html_generator.finalize();
-            new_node.literal = format!("<pre><code>{}</code></pre>\n", rendered).into_bytes();
+            new_node.literal = attributes
+                .unwrap_or_default()
+                .render(&rendered)
+                .into_bytes();
 
             data.value = NodeValue::HtmlBlock(new_node);
         }
     }
+
+    diagnostics
+}
+
+/// Replace math in the Markdown AST with MathML, in-place.
+///
+/// The math itself is found by `math::mark` before the document is parsed, and ends up in code spans (for inline math)
+/// or fenced codeblocks in the `math` language (for display math).
+///
+/// This function returns an error if any of the math is malformed.
+pub fn render_math<'a>(root: &'a AstNode<'a>) -> Result<()> {
+    let front_matter_lines = front_matter_lines(root);
+
+    for node in traverse(root) {
+        let (tex, display) = match node.data.borrow().value {
+            NodeValue::Code(ref literal) => {
+                let code = String::from_utf8_lossy(literal);
+
+                match code.chars().next() {
+                    Some(math::INLINE_MARKER) => {
+                        (code[math::INLINE_MARKER.len_utf8()..].to_owned(), false)
+                    }
+                    Some(math::DISPLAY_MARKER) => {
+                        (code[math::DISPLAY_MARKER.len_utf8()..].to_owned(), true)
+                    }
+                    _ => continue,
+                }
+            }
+            NodeValue::CodeBlock(ref codeblock)
+                if codeblock.info == math::MATH_LANGUAGE.as_bytes() =>
+            {
+                (
+                    String::from_utf8_lossy(&codeblock.literal).into_owned(),
+                    true,
+                )
+            }
+            _ => continue,
+        };
+
+        // Inline nodes don't know which line they're on, but the block that they're in does.
+        let line = node
+            .ancestors()
+            .map(|ancestor| ancestor.data.borrow().start_line)
+            .find(|&line| line != 0)
+            .unwrap_or(0)
+            + front_matter_lines;
+
+        let mathml = math::to_mathml(tex.trim(), display)
+            .map_err(|error| Error::msg(format!("math on line {line}: {error}")))?;
+
+        let mut data = node.data.borrow_mut();
+
+        data.value = match data.value {
+            NodeValue::CodeBlock(_) => {
+                let mut new_node = NodeHtmlBlock::default();
+                new_node.literal = format!("{mathml}\n").into_bytes();
+                NodeValue::HtmlBlock(new_node)
+            }
+            _ => NodeValue::HtmlInline(mathml.into_bytes()),
+        };
+    }
+
+    Ok(())
+}
+
+/// Replace the admonition markers in the Markdown AST with `<aside>` elements, in-place.
+///
+/// The markers themselves are added by `admonitions::mark` before the document is parsed. Everything between a pair of
+/// markers is left where it is, so it ends up inside of the `<aside>` once rendered.
+pub fn render_admonitions<'a>(root: &'a AstNode<'a>) {
+    for node in traverse(root) {
+        let mut data = node.data.borrow_mut();
+
+        let html = match data.value {
+            NodeValue::HtmlBlock(ref block) => {
+                admonitions::to_html(&String::from_utf8_lossy(&block.literal))
+            }
+            _ => None,
+        };
+
+        if let Some(html) = html {
+            let mut new_node = NodeHtmlBlock::default();
+            new_node.literal = html.into_bytes();
+            data.value = NodeValue::HtmlBlock(new_node);
+        }
+    }
+}
+
+/// Replace the images in the Markdown AST with responsive HTML, in-place.
+///
+/// Images on this site are resized into each configured width and format, and given a size (see `Images::render`).
+/// Every image is loaded lazily. Relative URLs are resolved against `page_url`, and `bundle` is the directory that the
+/// document is bundled in, if there is one.
+///
+/// This function returns an error if an image doesn't exist, or can't be resized.
+pub fn render_images<'a>(
+    root: &'a AstNode<'a>,
+    page_url: &str,
+    bundle: Option<&Path>,
+) -> Result<()> {
+    let images = IMAGES.read().unwrap();
+    let front_matter_lines = front_matter_lines(root);
+
+    // Images are replaced along with their alt text, which can't be done while traversing the document.
+    let nodes: Vec<_> = traverse(root)
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::Image(_)))
+        .collect();
+
+    for node in nodes {
+        let (url, title) = match node.data.borrow().value {
+            NodeValue::Image(ref link) => (
+                String::from_utf8_lossy(&link.url).into_owned(),
+                String::from_utf8_lossy(&link.title).into_owned(),
+            ),
+            _ => unreachable!(),
+        };
+
+        let html = images
+            .render(&url, &plain_text(node), &title, page_url, bundle)
+            .map_err(|error| {
+                let line = node
+                    .ancestors()
+                    .map(|ancestor| ancestor.data.borrow().start_line)
+                    .find(|&line| line != 0)
+                    .unwrap_or(0)
+                    + front_matter_lines;
+
+                Error::msg(format!("image on line {line}: {error}"))
+            })?;
+
+        for child in node.children() {
+            child.detach();
+        }
+
+        node.data.borrow_mut().value = NodeValue::HtmlInline(html.into_bytes());
+    }
+
+    Ok(())
+}
+
+/// Replace the shortcodes in the Markdown AST with their rendered HTML, in-place.
+///
+/// Shortcodes are rendered by `shortcodes::mark` before the document is parsed, leaving behind comments that end up in
+/// HTML blocks (for shortcodes on their own line) or inline HTML (for shortcodes in the middle of a paragraph).
+pub fn render_shortcodes<'a>(root: &'a AstNode<'a>, rendered: &[String]) {
+    if rendered.is_empty() {
+        return;
+    }
+
+    for node in traverse(root) {
+        let mut data = node.data.borrow_mut();
+
+        match data.value {
+            NodeValue::HtmlBlock(ref mut block) => {
+                if let Some(html) =
+                    shortcodes::to_html(&String::from_utf8_lossy(&block.literal), rendered)
+                {
+                    block.literal = html.into_bytes();
+                }
+            }
+            NodeValue::HtmlInline(ref mut literal) => {
+                if let Some(html) = shortcodes::to_html(&String::from_utf8_lossy(literal), rendered)
+                {
+                    *literal = html.into_bytes();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Return how many lines the document's front matter takes up.
+///
+/// Line numbers in the AST don't account for front matter, which is useless when pointing out problems in the original
+/// file.
+fn front_matter_lines<'a>(root: &'a AstNode<'a>) -> u32 {
+    traverse(root)
+        .find_map(|node| match node.data.borrow().value {
+            NodeValue::FrontMatter(ref front_matter) => {
+                Some(front_matter.iter().filter(|&&byte| byte == b'\n').count() as u32)
+            }
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// Extra attributes attached to a fenced codeblock.
+#[derive(Debug)]
+struct CodeAttributes {
+    line_numbers: bool,
+    highlighted: Vec<RangeInclusive<usize>>,
+    title: Option<String>,
+    start: i64,
+}
+
+impl Default for CodeAttributes {
+    fn default() -> Self {
+        Self {
+            line_numbers: false,
+            highlighted: Vec::new(),
+            title: None,
+            start: 1,
+        }
+    }
+}
+
+impl CodeAttributes {
+    /// Parse attributes from `source`, which is everything after the opening brace. Problems are reported to `warn`.
+    fn parse(source: &str, mut warn: impl FnMut(String)) -> Self {
+        let mut attributes = Self::default();
+
+        let table = match toml::from_str::<toml::value::Table>(&format!("attributes = {{{source}"))
+        {
+            Ok(mut table) => table.remove("attributes"),
+            Err(error) => {
+                warn(format!("invalid attributes: {error}"));
+                return attributes;
+            }
+        };
+
+        let Some(toml::Value::Table(table)) = table else {
+            return attributes;
+        };
+
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("lines", toml::Value::Boolean(lines)) => attributes.line_numbers = lines,
+                // Keeping `start` well within range means that line numbers can't overflow, however long the code is.
+                ("start", toml::Value::Integer(start)) if i32::try_from(start).is_ok() => {
+                    attributes.start = start
+                }
+                ("title", toml::Value::String(title)) => attributes.title = Some(title),
+                ("highlight", toml::Value::String(ranges)) => match parse_line_ranges(&ranges) {
+                    Some(ranges) => attributes.highlighted = ranges,
+                    None => warn(format!(
+                        "invalid line ranges `{ranges}` for attribute `highlight`"
+                    )),
+                },
+                ("lines" | "start" | "title" | "highlight", value) => {
+                    warn(format!("invalid value `{value}` for attribute `{key}`"))
+                }
+                (_, _) => warn(format!("unknown attribute `{key}`")),
+            }
+        }
+
+        attributes
+    }
+
+    /// Wrap already-highlighted HTML according to these attributes.
+    fn render(&self, highlighted: &str) -> String {
+        let body = match self.line_numbers || !self.highlighted.is_empty() {
+            false => highlighted.to_owned(),
+            true => split_lines(highlighted)
+                .into_iter()
+                .zip(1..)
+                .map(|(line, index)| {
+                    let class = match self.highlighted.iter().any(|range| range.contains(&index)) {
+                        true => "code-line code-line-highlighted",
+                        false => "code-line",
+                    };
+
+                    let number = match self.line_numbers {
+                        true => format!(
+                            "<span class=\"code-line-number\">{}</span>",
+                            self.start + index as i64 - 1
+                        ),
+                        false => String::new(),
+                    };
+
+                    format!("<span class=\"{class}\">{number}{line}\n</span>")
+                })
+                .collect(),
+        };
+
+        let code = format!("<pre><code>{body}</code></pre>\n");
+
+        match &self.title {
+            Some(title) => format!(
+                "<figure class=\"code-block\">\n<figcaption>{}</figcaption>\n{code}</figure>\n",
+                tera::escape_html(title)
+            ),
+            None => code,
+        }
+    }
+}
+
+/// Parse a list of line numbers and ranges, like "3-5,8".
+///
+/// This function returns `None` if any part of the list is invalid, including ranges that end before they start.
+fn parse_line_ranges(source: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    source
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+                .filter(|range| !range.is_empty()),
+            None => range.parse().ok().map(|line| line..=line),
+        })
+        .collect()
+}
+
+/// Split highlighted HTML into lines, without the trailing newline.
+///
+/// Highlighted spans can cover more than one line, so each line closes the spans that are still open at the end of it,
+/// and the next line re-opens them.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open_tags: Vec<&str> = Vec::new();
+
+    for raw_line in html.split('\n') {
+        let mut line = open_tags.concat();
+        let mut has_text = false;
+        let mut rest = raw_line;
+
+        // The highlighter only ever outputs `span` tags and escaped text, so this doesn't need to be very clever.
+        while !rest.is_empty() {
+            match rest.find('<') {
+                Some(0) => {
+                    let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+                    let tag = &rest[..end];
+
+                    match tag.starts_with("</") {
+                        true => drop(open_tags.pop()),
+                        false => open_tags.push(tag),
+                    }
+
+                    rest = &rest[end..];
+                }
+                Some(start) => {
+                    has_text = true;
+                    rest = &rest[start..];
+                }
+                None => {
+                    has_text = true;
+                    rest = "";
+                }
+            }
+        }
+
+        line.push_str(raw_line);
+        line.push_str(&"</span>".repeat(open_tags.len()));
+        lines.push((line, has_text));
+    }
+
+    // The code ends with a newline, which leaves behind an empty line (aside from any closing tags).
+    if lines.last().is_some_and(|(_, has_text)| !has_text) {
+        lines.pop();
+    }
+
+    lines.into_iter().map(|(line, _)| line).collect()
+}
+
+/// An entry in a page's table of contents.
+#[derive(Debug, Serialize)]
+pub struct TocEntry {
+    id: String,
+    title: String,
+    level: u32,
+    children: Vec<TocEntry>,
+}
+
+/// Give each heading in the Markdown AST a unique ID, returning a table of contents.
+///
+/// IDs are derived from the text of each heading, so they stay the same unless the heading itself changes. Like
+/// `highlight`, each heading is replaced with an inline HTML node. If enabled in the configuration, a link to the heading
+/// is added to the end of it.
+pub fn anchor_headings<'a>(root: &'a AstNode<'a>) -> Vec<TocEntry> {
+    let self_links = MARKDOWN_CONFIG.read().unwrap().heading_anchors();
+    let mut ids = HashSet::new();
+    let mut entries = Vec::new();
+
+    // The AST can't be modified while it's being traversed.
+    let headings: Vec<_> = traverse(root)
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::Heading(_)))
+        .collect();
+
+    for node in headings {
+        let NodeValue::Heading(NodeHeading { level, .. }) = node.data.borrow().value else {
+            continue;
+        };
+
+        let title = plain_text(node);
+        let base_id = match slugify(&title, '-') {
+            id if id.is_empty() => "section".to_owned(),
+            id => id,
+        };
+
+        let id = (1..)
+            .map(|n| match n {
+                1 => base_id.clone(),
+                _ => format!("{base_id}-{n}"),
+            })
+            .find(|id| !ids.contains(id))
+            .unwrap();
+
+        ids.insert(id.clone());
+
+        let children: Vec<_> = node.children().collect();
+        let mut html = format!("<h{level} id=\"{id}\">");
+
+        for child in children {
+            html.push_str(&render(child));
+            child.detach();
+        }
+
+        if self_links {
+            html.push_str(&format!(
+                "<a class=\"heading-anchor\" href=\"#{id}\" aria-label=\"Link to this section\">#</a>"
+            ));
+        }
+
+        html.push_str(&format!("</h{level}>\n"));
+
+        let mut new_node = NodeHtmlBlock::default();
+        new_node.literal = html.into_bytes();
+        node.data.borrow_mut().value = NodeValue::HtmlBlock(new_node);
+
+        entries.push(TocEntry {
+            id,
+            title,
+            level,
+            children: Vec::new(),
+        });
+    }
+
+    nest_toc(&mut entries.into_iter().peekable(), 0)
+}
+
+/// Nest each entry in the table of contents under the closest heading before it with a lower level.
+fn nest_toc(
+    entries: &mut Peekable<impl Iterator<Item = TocEntry>>,
+    parent_level: u32,
+) -> Vec<TocEntry> {
+    let mut nested = Vec::new();
+
+    while let Some(mut entry) = entries.next_if(|entry| entry.level > parent_level) {
+        entry.children = nest_toc(entries, entry.level);
+        nested.push(entry);
+    }
+
+    nested
+}
+
+/// Return the text inside of `node`, without any formatting.
+///
+/// Code blocks are skipped, and separate blocks (like paragraphs) are separated by a space.
+pub fn plain_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+
+    for node in traverse(node) {
+        match &node.data.borrow().value {
+            NodeValue::Text(bytes) | NodeValue::Code(bytes) => {
+                text.push_str(&String::from_utf8_lossy(bytes))
+            }
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            value if value.block() && !text.is_empty() => text.push(' '),
+            _ => {}
+        }
+    }
+
+    text
+}
+
+/// Count the words in the Markdown AST, skipping any code blocks.
+pub fn word_count<'a>(document: &'a AstNode<'a>) -> usize {
+    plain_text(document).split_whitespace().count()
+}
+
+/// Estimate how many minutes it takes to read `words` words, rounding up.
+pub fn reading_time(words: usize) -> usize {
+    let words_per_minute = MARKDOWN_CONFIG.read().unwrap().words_per_minute();
+
+    words.div_ceil(words_per_minute).max(1)
+}
+
+/// Turn `text` into something that can be used in a URL or file name.
+///
+/// Letters and numbers are lowercased and kept as-is, while everything else is collapsed into a single `separator`.
+pub fn slugify(text: &str, separator: char) -> String {
+    let mut slug = String::with_capacity(text.len());
+
+    for word in text.split(|char: char| !char.is_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+
+        if !slug.is_empty() {
+            slug.push(separator);
+        }
+
+        slug.extend(word.chars().flat_map(char::to_lowercase));
+    }
+
+    slug
 }
 
 /// Extract a "preview" paragraph from the Markdown AST.
 ///
-/// Returns the first paragraph of text content in the AST, truncated at `character_limit` characters. Overflow is
-/// represented by appending a space followed by "[...]".
+/// Returns the text of the first paragraph in the AST, truncated at `character_limit` characters. Overflow is
+/// represented by appending a space followed by "[...]". Formatting is dropped, but the text inside of links and inline
+/// code is kept.
 ///
 /// This function returns `None` if the AST does not contain a paragraph, or if the paragraph is empty.
 pub fn preview<'a>(document: &'a AstNode<'a>, character_limit: usize) -> Option<String> {
     let first_paragraph = document
         .children()
-        .find(|node| matches!(node.data.borrow().value, NodeValue::Paragraph))?
-        .children();
+        .find(|node| matches!(node.data.borrow().value, NodeValue::Paragraph))?;
 
-    fn process_text<'a>(buffer: &mut String, nodes: impl Iterator<Item = &'a AstNode<'a>>) {
-        for node in nodes {
-            match &node.data.borrow().value {
-                NodeValue::Text(bytes) => buffer.push_str(&String::from_utf8_lossy(bytes)),
-                NodeValue::SoftBreak => buffer.push(' '),
-                NodeValue::LineBreak => buffer.push('\n'),
-                NodeValue::Emph
-                | NodeValue::Strong
-                | NodeValue::Strikethrough
-                | NodeValue::Superscript => process_text(buffer, node.children()),
-                _ => continue,
-            }
-        }
-    }
-
-    let mut preview = String::new();
-    process_text(&mut preview, first_paragraph);
+    let preview = plain_text(first_paragraph).trim().to_owned();
 
     if preview.is_empty() {
         return None;
@@ -174,3 +1049,237 @@ pub fn preview<'a>(document: &'a AstNode<'a>, character_limit: usize) -> Option<
         None => Some(preview),
     }
 }
+
+/// Extract a summary from the Markdown AST, returning a tuple of (HTML, text).
+///
+/// If the document contains a `<!-- more -->` marker on a line of its own, everything before the marker is used as the
+/// summary, and keeps its formatting. Otherwise, this falls back to `preview`.
+///
+/// This function returns `None` if the summary would be empty.
+pub fn summary<'a>(document: &'a AstNode<'a>, character_limit: usize) -> Option<(String, String)> {
+    let Some(marker) = document.children().position(is_summary_marker) else {
+        let text = preview(document, character_limit)?;
+        let html = format!("<p>{}</p>\n", tera::escape_html(&text));
+
+        return Some((html, text));
+    };
+
+    let nodes: Vec<_> = document.children().take(marker).collect();
+    let html: String = nodes.iter().map(|node| render(node)).collect();
+    let text = nodes
+        .iter()
+        .map(|node| plain_text(node).trim().to_owned())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!text.is_empty()).then_some((html, text))
+}
+
+/// Whether `node` is a `<!-- more -->` marker.
+fn is_summary_marker<'a>(node: &'a AstNode<'a>) -> bool {
+    match &node.data.borrow().value {
+        NodeValue::HtmlBlock(block) => {
+            let literal = String::from_utf8_lossy(&block.literal);
+            let compact: String = literal.split_whitespace().collect();
+
+            compact.eq_ignore_ascii_case("<!--more-->")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load the shortcodes that come with the site.
+    fn load_site_shortcodes() {
+        load_shortcodes(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("content/templates/shortcodes"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rewrite_urls_decodes_shortcode_urls() {
+        load_site_shortcodes();
+
+        let (_, rendered) = shortcodes::mark(
+            "{{< embed src=\"/arewemicrowaveyet/index.html\" title=\"Microwaves\" >}}",
+        )
+        .unwrap();
+
+        // Shortcode arguments are escaped by Tera, which turns `/` into `&#x2F;`.
+        assert!(rendered[0].contains("&#x2F;"));
+
+        let base = Url::parse("https://kaylynn.gay/blog/post/example").unwrap();
+        let html = rewrite_urls(&rendered[0], |url| base.join(url).ok().map(String::from));
+
+        assert!(
+            html.contains("src=\"https://kaylynn.gay/arewemicrowaveyet/index.html\""),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn rebase_urls_resolves_shortcode_urls_in_bundles() {
+        load_site_shortcodes();
+
+        let arena = Arena::new();
+        let source = "---\n---\n\n{{< figure src=\"cat.webp\" >}}\n\n\
+            {{< embed src=\"/arewemicrowaveyet/index.html\" title=\"Microwaves\" >}}\n\n[Notes](notes.txt)\n";
+
+        let (_, document, _) = parse::<toml::value::Table>(&arena, source).unwrap();
+        rebase_urls(document, "/blog/post/bundled/");
+        let html = render(document);
+
+        assert!(
+            html.contains("src=\"/blog/post/bundled/cat.webp\""),
+            "{html}"
+        );
+        assert!(
+            html.contains("href=\"/blog/post/bundled/notes.txt\""),
+            "{html}"
+        );
+        assert!(!html.contains("/blog/post/bundled/&#x2F;"), "{html}");
+    }
+
+    #[test]
+    fn rewrite_urls_escapes_rewritten_urls() {
+        let html = rewrite_urls("<a href=\"?a=1&amp;b=2\">", |url| {
+            Some(format!("/page{url}"))
+        });
+
+        assert_eq!(html, "<a href=\"/page?a=1&amp;b=2\">");
+    }
+
+    #[test]
+    fn rewrite_urls_leaves_unchanged_urls_alone() {
+        let html = "<img src=\"&#x2F;cat.webp\" srcset=\"a.webp 480w, b.webp 960w\" />";
+
+        assert_eq!(rewrite_urls(html, |_| None), html);
+    }
+
+    #[test]
+    fn rewrite_urls_rewrites_each_srcset_candidate() {
+        let html = rewrite_urls("<source srcset=\"a.webp 480w, b.webp 960w\" />", |url| {
+            Some(format!("/images/{url}"))
+        });
+
+        assert_eq!(
+            html,
+            "<source srcset=\"/images/a.webp 480w, /images/b.webp 960w\" />"
+        );
+    }
+
+    #[test]
+    fn parse_line_ranges_accepts_lines_and_ranges() {
+        assert_eq!(
+            parse_line_ranges("3-5, 8,10 - 10,"),
+            Some(vec![3..=5, 8..=8, 10..=10])
+        );
+        assert_eq!(parse_line_ranges(""), Some(vec![]));
+    }
+
+    #[test]
+    fn parse_line_ranges_keeps_overlapping_ranges() {
+        assert_eq!(
+            parse_line_ranges("1-3,2-4,2"),
+            Some(vec![1..=3, 2..=4, 2..=2])
+        );
+    }
+
+    #[test]
+    fn parse_line_ranges_rejects_invalid_ranges() {
+        for source in ["5-3", "1-3,5-3", "a", "1-", "-2", "1-2-3", "-1", "1.5"] {
+            assert_eq!(parse_line_ranges(source), None, "{source}");
+        }
+    }
+
+    #[test]
+    fn split_lines_reopens_spans() {
+        let html = "<span class=\"a\">one\n<span class=\"b\">two</span>\nthree</span>\n";
+
+        assert_eq!(
+            split_lines(html),
+            [
+                "<span class=\"a\">one</span>",
+                "<span class=\"a\"><span class=\"b\">two</span></span>",
+                "<span class=\"a\">three</span>",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_lines_keeps_blank_lines() {
+        assert_eq!(split_lines("one\n\ntwo\n"), ["one", "", "two"]);
+        assert_eq!(
+            split_lines("<span>one\n\n</span>"),
+            ["<span>one</span>", "<span></span>"]
+        );
+    }
+
+    #[test]
+    fn code_attributes_report_invalid_values() {
+        let mut warnings = Vec::new();
+        let attributes = CodeAttributes::parse(
+            "lines = true, start = 9223372036854775807, highlight = \"5-3\", colour = \"red\"}",
+            |warning| warnings.push(warning),
+        );
+
+        assert!(attributes.line_numbers);
+        assert_eq!(attributes.start, 1);
+        assert!(attributes.highlighted.is_empty());
+        assert_eq!(
+            warnings,
+            [
+                "unknown attribute `colour`",
+                "invalid line ranges `5-3` for attribute `highlight`",
+                "invalid value `9223372036854775807` for attribute `start`",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_attributes_highlight_overlapping_ranges_once() {
+        let attributes = CodeAttributes::parse(
+            "lines = true, start = -1, highlight = \"1-2,2-3\"}",
+            |warning| panic!("unexpected warning: {warning}"),
+        );
+
+        assert_eq!(
+            attributes.render("a\nb\nc\nd\n"),
+            "<pre><code>\
+             <span class=\"code-line code-line-highlighted\"><span class=\"code-line-number\">-1</span>a\n</span>\
+             <span class=\"code-line code-line-highlighted\"><span class=\"code-line-number\">0</span>b\n</span>\
+             <span class=\"code-line code-line-highlighted\"><span class=\"code-line-number\">1</span>c\n</span>\
+             <span class=\"code-line\"><span class=\"code-line-number\">2</span>d\n</span>\
+             </code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn decode_entities_decodes_references() {
+        assert_eq!(
+            decode_entities("&#x2F;a&#47;b&amp;c&lt;&gt;&quot;&apos;"),
+            "/a/b&c<>\"'"
+        );
+    }
+
+    #[test]
+    fn decode_entities_leaves_unknown_references_alone() {
+        for text in [
+            "&nbsp;",
+            "&#;",
+            "&#x;",
+            "&#+47;",
+            "&#xZZ;",
+            "&#1114112;",
+            "a & b",
+            "&amp",
+        ] {
+            assert_eq!(decode_entities(text), text);
+        }
+    }
+}
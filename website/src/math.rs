@@ -0,0 +1,309 @@
+use latex2mathml::DisplayStyle;
+
+use crate::markdown;
+
+/// Code spans that start with this character contain inline math, rather than code.
+pub const INLINE_MARKER: char = '\u{E000}';
+/// Code spans that start with this character contain display math, rather than code.
+pub const DISPLAY_MARKER: char = '\u{E001}';
+/// The language given to fenced codeblocks that contain display math.
+pub const MATH_LANGUAGE: &str = "math";
+
+/// The elements that latex2mathml produces. Anything else that looks like a tag in its output is really just text.
+const MATHML_ELEMENTS: [&str; 22] = [
+    "math",
+    "mfrac",
+    "mi",
+    "mmultiscripts",
+    "mn",
+    "mo",
+    "mover",
+    "mprescripts",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "mstyle",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+];
+
+/// Mark up the math in Markdown source, so that comrak leaves it alone.
+///
+/// TeX is full of characters that mean something to Markdown (backslashes and asterisks, mostly), so `$...$` and
+/// `$$...$$` spans are turned into code spans, which comrak keeps verbatim. These start with `INLINE_MARKER` or
+/// `DISPLAY_MARKER` so that they can be told apart from actual code. A line containing only `$$` opens or closes a block
+/// of display math, which is turned into a fenced codeblock in the `math` language.
+///
+/// Like pandoc, `$` only opens inline math when it's followed by something other than whitespace, and only closes it
+/// when it follows something other than whitespace and isn't followed by a digit. This keeps prices from turning into
+/// math. Math can't contain backticks, and front matter and code are left untouched. No lines are added or removed.
+pub fn mark(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut in_display = false;
+
+    for (line, is_text) in markdown::source_lines(source) {
+        let trimmed = line.trim();
+
+        if in_display && trimmed == "$$" {
+            in_display = false;
+            output.push_str(&line.replacen("$$", "```", 1));
+        } else if in_display || !is_text {
+            output.push_str(line);
+        } else if trimmed == "$$" {
+            in_display = true;
+            output.push_str(&line.replacen("$$", &format!("```{MATH_LANGUAGE}"), 1));
+        } else {
+            mark_inline(line, &mut output);
+        }
+    }
+
+    output
+}
+
+/// Convert TeX to MathML, returning a description of the problem if the TeX is malformed.
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let style = match display {
+        true => DisplayStyle::Block,
+        false => DisplayStyle::Inline,
+    };
+
+    let mathml = latex2mathml::latex_to_mathml(tex, style).map_err(|error| error.to_string())?;
+
+    // Some problems (like unknown commands) are written into the output instead of being returned as errors.
+    if let Some((_, problem)) = mathml.split_once("[PARSE ERROR: ") {
+        let problem = problem
+            .split_once("]</mtext>")
+            .map_or(problem, |(problem, _)| problem);
+        return Err(format!("unable to parse `{tex}`: {problem}"));
+    }
+
+    Ok(escape_text(&mathml))
+}
+
+/// Escape the text inside of latex2mathml's output, which it doesn't do by itself.
+fn escape_text(mathml: &str) -> String {
+    let mut output = String::with_capacity(mathml.len());
+    let mut rest = mathml;
+
+    while let Some(character) = rest.chars().next() {
+        let tag = match character {
+            '<' => rest
+                .find('>')
+                .map(|end| &rest[..=end])
+                .filter(|tag| is_mathml_tag(tag)),
+            _ => None,
+        };
+
+        match (tag, character) {
+            (Some(tag), _) => output.push_str(tag),
+            (None, '<') => output.push_str("&lt;"),
+            (None, '>') => output.push_str("&gt;"),
+            (None, '&') => output.push_str("&amp;"),
+            (None, character) => output.push(character),
+        }
+
+        rest = &rest[tag.map_or(character.len_utf8(), str::len)..];
+    }
+
+    output
+}
+
+fn is_mathml_tag(tag: &str) -> bool {
+    let Some(name) = tag.strip_prefix('<') else {
+        return false;
+    };
+
+    let name = name.strip_prefix('/').unwrap_or(name);
+    let end = name
+        .find(|character: char| !character.is_ascii_alphabetic())
+        .unwrap_or(name.len());
+
+    MATHML_ELEMENTS.contains(&&name[..end]) && name[end..].starts_with([' ', '/', '>'])
+}
+
+/// Mark up the inline math in a single line, appending the result to `output`.
+fn mark_inline(line: &str, output: &mut String) {
+    let mut rest = line;
+
+    while let Some(start) = rest.find(['\\', '`', '$']) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let length = match rest.as_bytes()[0] {
+            // Escaped characters (including `\$`) are kept as they are.
+            b'\\' => rest[1..]
+                .chars()
+                .next()
+                .map_or(1, |next| 1 + next.len_utf8()),
+            // So are code spans, up until the matching run of backticks.
+            b'`' => markdown::code_span_length(rest),
+            _ => match math_span(rest) {
+                Some((tex, marker, length)) => {
+                    output.push_str(&code_span(tex, marker));
+                    rest = &rest[length..];
+                    continue;
+                }
+                None => 1,
+            },
+        };
+
+        output.push_str(&rest[..length]);
+        rest = &rest[length..];
+    }
+
+    output.push_str(rest);
+}
+
+/// Find the math at the start of `text`, returning the TeX inside of it, the marker to use, and its total length.
+fn math_span(text: &str) -> Option<(&str, char, usize)> {
+    let (delimiter, marker) = match text.starts_with("$$") {
+        true => ("$$", DISPLAY_MARKER),
+        false => ("$", INLINE_MARKER),
+    };
+
+    let body = &text[delimiter.len()..];
+
+    if body.is_empty() || body.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let mut searched = 0;
+
+    loop {
+        let end = searched + body[searched..].find(delimiter)?;
+        let tex = &body[..end];
+        let after = &body[end + delimiter.len()..];
+
+        // Math never runs into a code span.
+        if tex.contains('`') {
+            return None;
+        }
+
+        let closes = !tex.is_empty()
+            && !tex.ends_with(char::is_whitespace)
+            && !tex.ends_with('\\')
+            && !after.starts_with(|character: char| character.is_ascii_digit());
+
+        if closes {
+            return Some((tex, marker, end + delimiter.len() * 2));
+        }
+
+        searched = end + 1;
+    }
+}
+
+/// Wrap `tex` in a code span that starts with `marker`.
+fn code_span(tex: &str, marker: char) -> String {
+    let longest_run = tex
+        .split(|character| character != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+
+    let backticks = "`".repeat(longest_run + 1);
+
+    // Code spans can't end with a backtick, unless they're padded with spaces (which are stripped again).
+    let padding = match tex.ends_with('`') {
+        true => " ",
+        false => "",
+    };
+
+    format!("{backticks}{padding}{marker}{tex}{padding}{backticks}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mark_inline_math() {
+        assert_eq!(
+            mark("The area is $\\pi r^2$, roughly.\n"),
+            format!("The area is `{INLINE_MARKER}\\pi r^2`, roughly.\n")
+        );
+    }
+
+    #[test]
+    fn mark_display_math() {
+        assert_eq!(
+            mark("So $$\\frac{a}{b}$$ it is.\n"),
+            format!("So `{DISPLAY_MARKER}\\frac{{a}}{{b}}` it is.\n")
+        );
+
+        assert_eq!(
+            mark("$$\nx^2 * y^2\n$$\n"),
+            format!("```{MATH_LANGUAGE}\nx^2 * y^2\n```\n")
+        );
+    }
+
+    #[test]
+    fn mark_leaves_prices_alone() {
+        for source in [
+            "It costs $5 and $10.\n",
+            "Somewhere between $5 and $10 or $20.\n",
+            "A $ sign on its own, and another $ here.\n",
+            "$x$5 isn't math either.\n",
+        ] {
+            assert_eq!(mark(source), source);
+        }
+    }
+
+    #[test]
+    fn mark_leaves_escaped_dollars_alone() {
+        assert_eq!(mark("\\$x$ costs \\$5\n"), "\\$x$ costs \\$5\n");
+        assert_eq!(mark("$a \\$ b$\n"), format!("`{INLINE_MARKER}a \\$ b`\n"));
+    }
+
+    #[test]
+    fn mark_doesnt_close_math_after_a_backslash() {
+        assert_eq!(mark("$a\\$ and $\n"), "$a\\$ and $\n");
+    }
+
+    #[test]
+    fn mark_leaves_code_alone() {
+        for source in [
+            "Use `$x$` in shell scripts.\n",
+            "Use ``a ` $x$`` here.\n",
+            "```sh\necho $x$\n```\n",
+            "---\ntitle = \"$x$\"\n---\n",
+        ] {
+            assert_eq!(mark(source), source);
+        }
+    }
+
+    #[test]
+    fn mark_doesnt_run_math_into_code() {
+        assert_eq!(mark("$a `b$` c\n"), "$a `b$` c\n");
+    }
+
+    #[test]
+    fn mark_keeps_lines_in_place() {
+        let source = "$$\nx\n\n$y$\n";
+        let marked = mark(source);
+
+        assert_eq!(marked.lines().count(), source.lines().count());
+        assert!(marked.starts_with(&format!("```{MATH_LANGUAGE}\n")));
+    }
+
+    #[test]
+    fn to_mathml_escapes_text() {
+        let mathml = to_mathml("a < b", false).unwrap();
+
+        assert!(mathml.starts_with("<math"), "{mathml}");
+        assert!(mathml.contains("&lt;"), "{mathml}");
+    }
+
+    #[test]
+    fn to_mathml_reports_problems() {
+        assert!(to_mathml("\\notacommand", false).is_err());
+        assert!(to_mathml("\\frac{a", true).is_err());
+    }
+}
@@ -1,24 +1,27 @@
 use axum::response::Html;
-use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
 use comrak::Arena;
-use serde::{de::DeserializeOwned, de::Error, Deserialize, Deserializer};
-use std::{borrow::Cow, path::Path};
+use serde::{de::DeserializeOwned, de::Error, Deserialize, Deserializer, Serialize};
+use std::{borrow::Cow, collections::HashSet, path::Path};
 use tera::{Context, Value};
 use toml::value::Datetime as TomlDateTime;
 
 use crate::{
     context,
     error::Result,
-    markdown::{self, NodeArena, NodeRef},
+    markdown::{self, Diagnostics, NodeArena, NodeRef},
     templates::Engine,
 };
 
 const PREVIEW_CHARACTER_LIMIT: usize = 200;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Page {
     template_name: Cow<'static, str>,
     context: Context,
+    /// Values that are computed from other pages, rather than from the page itself.
+    derived: Context,
+    diagnostics: Diagnostics,
 }
 
 impl Page {
@@ -26,24 +29,47 @@ impl Page {
         Self {
             template_name: template_name.into(),
             context,
+            derived: Context::new(),
+            diagnostics: Diagnostics::default(),
         }
     }
 
     pub fn simple(path: impl AsRef<Path>) -> Result<Self> {
         let content = std::fs::read_to_string(path)?;
         let arena = Arena::new();
-        let page = Self::build::<StaticMetadata>(&arena, &content)?;
+        // Simple pages are served from the root of the site (like `/about`), so that's what relative URLs are relative to.
+        let page = Self::build::<StaticMetadata>(&arena, &content, "/", None)?;
 
         Ok(page)
     }
 
-    pub fn build<'a, M>(arena: NodeArena<'a>, content: &str) -> Result<Self>
+    /// Build a page from raw Markdown source.
+    ///
+    /// `url` is where the page is served from, which relative URLs in the page are resolved against. `bundle` is the
+    /// directory of the bundle that the page belongs to (served from `url`), for posts that come with their own assets.
+    pub fn build<'a, M>(
+        arena: NodeArena<'a>,
+        content: &str,
+        url: &str,
+        bundle: Option<&Path>,
+    ) -> Result<Self>
     where
         M: DeserializeOwned + IntoPage + 'static,
     {
-        let (metadata, document) = markdown::parse::<M>(&arena, content)?;
+        let (metadata, document, diagnostics) = markdown::parse::<M>(arena, content)?;
 
-        Ok(metadata.into_page(document))
+        if bundle.is_some() {
+            markdown::rebase_urls(document, url);
+        }
+
+        markdown::render_images(document, url, bundle)?;
+
+        let page = metadata.into_page(document);
+
+        Ok(Self {
+            diagnostics,
+            ..page
+        })
     }
 
     pub fn title(&self) -> Option<String> {
@@ -60,6 +86,39 @@ impl Page {
             .map(str::to_owned)
     }
 
+    pub fn tags(&self) -> Vec<Tag> {
+        self.context
+            .get("tags")
+            .cloned()
+            .and_then(|tags| serde_json::from_value(tags).ok())
+            .unwrap_or_default()
+    }
+
+    /// Return the series that this page is a part of, if there is one.
+    pub fn series(&self) -> Option<Tag> {
+        self.context
+            .get("series")
+            .cloned()
+            .and_then(|series| serde_json::from_value(series).ok())
+    }
+
+    pub fn is_draft(&self) -> bool {
+        self.context
+            .get("draft")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    /// Whether this page should be left out of listings, feeds and the sitemap.
+    ///
+    /// Unlisted pages can still be visited directly.
+    pub fn is_unlisted(&self) -> bool {
+        self.context
+            .get("unlisted")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+
     pub fn published(&self) -> Option<DateTime<FixedOffset>> {
         self.context
             .get("published")
@@ -67,17 +126,74 @@ impl Page {
             .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
     }
 
+    pub fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        self.context
+            .get("updated")
+            .and_then(Value::as_str)
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+    }
+
+    /// Return the rendered HTML content of this page.
+    pub fn content(&self) -> Option<String> {
+        self.context
+            .get("content")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+    }
+
     pub fn render(&self, engine: &Engine) -> Result<Html<String>> {
-        let result = engine.render(&format!("{}.html.tera", self.template_name), &self.context)?;
+        let mut context = self.context.clone();
+        context.extend(self.derived.clone());
+
+        let result = engine.render(&format!("{}.html.tera", self.template_name), &context)?;
 
         Ok(result)
     }
 
+    /// Replace the values that were computed from other pages.
+    ///
+    /// These are available when rendering, but aren't part of `Page::context`, so they don't affect whether a page is
+    /// considered to have changed.
+    pub fn set_derived(&mut self, derived: Context) {
+        self.derived = derived;
+    }
+
+    /// Return any problems that were found while importing this page, which weren't serious enough to stop it from
+    /// being imported.
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
     pub fn context(&self) -> &Context {
         &self.context
     }
 }
 
+/// A tag attached to a blog post.
+///
+/// Series are named in exactly the same way as tags, so this is used for those too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    name: String,
+    slug: String,
+}
+
+impl Tag {
+    pub fn new(name: String) -> Self {
+        let slug = markdown::slugify(&name, '-');
+
+        Self { name, slug }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+}
+
 pub trait IntoPage {
     fn into_page<'a>(self, document: NodeRef<'a>) -> Page;
 }
@@ -95,15 +211,28 @@ pub struct PostMetadata {
     description: Option<String>,
     #[serde(deserialize_with = "toml_date")]
     published: DateTime<Local>,
+    #[serde(default, deserialize_with = "optional_toml_date")]
+    updated: Option<DateTime<Local>>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    unlisted: bool,
+    #[serde(default)]
+    series: Option<String>,
 }
 
 impl IntoPage for StaticMetadata {
     fn into_page<'a>(self, document: NodeRef<'a>) -> Page {
+        let toc = markdown::anchor_headings(document);
+
         Page::new(
             "page",
             context! {
                 "title" => self.title,
                 "description" => self.description,
+                "toc" => toc,
                 "content" => markdown::render(document),
             },
         )
@@ -112,17 +241,48 @@ impl IntoPage for StaticMetadata {
 
 impl IntoPage for PostMetadata {
     fn into_page<'a>(self, document: NodeRef<'a>) -> Page {
+        let (summary_html, summary_text) =
+            markdown::summary(document, PREVIEW_CHARACTER_LIMIT).unzip();
+
         let description = self
             .description
-            .or_else(|| markdown::preview(document, PREVIEW_CHARACTER_LIMIT))
+            .or_else(|| summary_text.clone())
             .unwrap_or_else(|| "(no description provided)".to_owned());
 
+        // Tags that only differ in spelling (like "Rust" and "rust") are the same tag, so only the first one is kept.
+        let mut slugs = HashSet::new();
+        let tags: Vec<_> = self
+            .tags
+            .into_iter()
+            .map(Tag::new)
+            .filter(|tag| !tag.slug().is_empty() && slugs.insert(tag.slug().to_owned()))
+            .collect();
+
+        let series = self
+            .series
+            .map(Tag::new)
+            .filter(|series| !series.slug().is_empty());
+
+        // Headings are replaced with HTML when they're given IDs, so words need to be counted beforehand.
+        let word_count = markdown::word_count(document);
+        let toc = markdown::anchor_headings(document);
+
         Page::new(
             "post",
             context! {
                 "title" => self.title,
                 "description" => description,
                 "published" => self.published.to_rfc3339(),
+                "updated" => self.updated.as_ref().map(DateTime::to_rfc3339),
+                "tags" => tags,
+                "draft" => self.draft,
+                "unlisted" => self.unlisted,
+                "series" => series,
+                "summary_html" => summary_html,
+                "summary_text" => summary_text,
+                "word_count" => word_count,
+                "reading_time" => markdown::reading_time(word_count),
+                "toc" => toc,
                 "content" => markdown::render(document),
                 "is_blog_post" => true,
             },
@@ -136,7 +296,17 @@ where
 {
     let date = TomlDateTime::deserialize(deserializer)?.to_string();
 
-    Local
-        .datetime_from_str(&date, "%Y-%m-%dT%H:%M:%S")
-        .map_err(|_| D::Error::custom("failed to parse toml date"))
+    NaiveDateTime::parse_from_str(&date, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .and_then(|date| Local.from_local_datetime(&date).single())
+        .ok_or_else(|| D::Error::custom("failed to parse toml date"))
+}
+
+fn optional_toml_date<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<DateTime<Local>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    toml_date(deserializer).map(Some)
 }
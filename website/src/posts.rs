@@ -1,53 +1,249 @@
-use chrono::DateTime;
+use atom_syndication::Feed;
+use chrono::{DateTime, Datelike, FixedOffset, Local};
 use comrak::Arena;
 use indexmap::IndexMap;
-use rss::{Channel, ChannelBuilder, GuidBuilder, ImageBuilder, ItemBuilder};
-use std::{borrow::Borrow, ffi::OsStr, hash::Hash, io::Result as IoResult, path::Path};
+use rss::Channel;
+use serde::Serialize;
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    ffi::OsStr,
+    hash::Hash,
+    io::Result as IoResult,
+    path::{Component, Path, PathBuf},
+};
+use tera::{Context, Value};
 use tracing::{event, Level};
 
-use crate::{page::Page, page::PostMetadata};
+use crate::{
+    context,
+    error::Error,
+    feeds::{self, JsonFeed},
+    markdown::Fallback,
+    page::{Page, PostMetadata},
+    state::Config,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Posts {
     pages: IndexMap<String, Page>,
     rss: Channel,
+    atom: Feed,
+    json: JsonFeed,
+    tags: IndexMap<String, TaggedPosts>,
+    series: IndexMap<String, TaggedPosts>,
+    archive: Archive,
+    bundles: IndexMap<String, PathBuf>,
+    next_scheduled: Option<DateTime<FixedOffset>>,
+}
+
+/// The file that holds the post itself, in a directory that bundles a post with its assets.
+pub const BUNDLE_INDEX: &str = "index.md";
+
+/// The most related posts that are linked to from each post.
+const RELATED_POST_LIMIT: usize = 3;
+
+/// How related two posts need to be before they're linked to each other.
+///
+/// Each shared tag counts for 1, and the overlap between the words used in each post counts for somewhere between 0 and
+/// 1.
+const RELATED_POST_THRESHOLD: f64 = 0.15;
+
+/// Words that are too common to say anything about how related two posts are.
+const STOP_WORDS: &[&str] = &[
+    "about", "also", "because", "been", "being", "could", "does", "doing", "from", "have", "here",
+    "into", "just", "like", "more", "much", "only", "over", "really", "same", "some", "than",
+    "that", "their", "them", "then", "there", "these", "they", "thing", "things", "this", "those",
+    "very", "what", "when", "where", "which", "while", "will", "with", "would", "your",
+];
+
+/// The slugs of every post, grouped by the year and then the month that they were published in.
+///
+/// Both years and months are ordered newest first.
+pub type Archive = IndexMap<i32, IndexMap<u32, Vec<String>>>;
+
+/// A tag or series, along with the slugs of every post in it.
+#[derive(Debug, Clone)]
+pub struct TaggedPosts {
+    name: String,
+    posts: Vec<String>,
+}
+
+impl TaggedPosts {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn posts(&self) -> &[String] {
+        &self.posts
+    }
+}
+
+/// A summary of what changed when refreshing posts.
+#[derive(Debug, Default, Serialize)]
+pub struct RefreshReport {
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+    failed: IndexMap<String, String>,
+    warnings: IndexMap<String, Vec<String>>,
+    fallbacks: IndexMap<String, Vec<Fallback>>,
+}
+
+impl RefreshReport {
+    /// Return the posts that failed to import, along with the reason why.
+    pub fn failed(&self) -> &IndexMap<String, String> {
+        &self.failed
+    }
+
+    /// Return the posts that imported with warnings, along with the warnings themselves.
+    pub fn warnings(&self) -> &IndexMap<String, Vec<String>> {
+        &self.warnings
+    }
+
+    /// Return the posts that have code blocks which couldn't be highlighted, along with the code blocks themselves.
+    pub fn fallbacks(&self) -> &IndexMap<String, Vec<Fallback>> {
+        &self.fallbacks
+    }
 }
 
 impl Posts {
     pub fn new() -> Self {
         let pages = Default::default();
-        let rss = rss_channel(&pages);
+        let rss = feeds::rss_channel(&pages, &Default::default(), &Default::default());
+        let atom = feeds::atom_feed(&pages, &Default::default(), &Default::default());
+        let json = feeds::json_feed(&pages, &Default::default(), &Default::default());
+        let tags = Default::default();
+        let series = Default::default();
+        let archive = Default::default();
 
-        Posts { pages, rss }
+        Posts {
+            pages,
+            rss,
+            atom,
+            json,
+            tags,
+            series,
+            archive,
+            bundles: IndexMap::new(),
+            next_scheduled: None,
+        }
     }
 
-    /// Read posts from `directory` and update this `Posts` instance.
+    /// Read posts from the `blog-pages` directory and update this `Posts` instance.
+    ///
+    /// Each post is either a Markdown file, or a directory containing an `index.md` file alongside the post's assets.
+    /// Posts in a directory are named after the directory, and relative URLs in them point to the directory.
+    ///
+    /// If a post that was previously imported fails to import, the previous version of that post is kept around.
+    /// Drafts are skipped unless the configuration says otherwise, and posts that are scheduled to be published in the
+    /// future are skipped until their publish date has passed.
+    ///
+    /// Returns a summary of which posts were added, changed, removed or failed to import.
     #[tracing::instrument(
-        skip(self, directory),
-        fields(directory = %directory.as_ref().display())
+        skip(self, config),
+        fields(directory = %config.content_dir().join("blog-pages").display())
     )]
-    pub fn refresh(&mut self, directory: &impl AsRef<Path>) -> IoResult<()> {
+    pub fn refresh(&mut self, config: &Config) -> IoResult<RefreshReport> {
         let arena = Arena::new();
+        let now = Local::now();
 
         let mut pages = IndexMap::new();
-        let mut entries = std::fs::read_dir(directory.as_ref())?;
-        let mut error_count = 0;
+        let mut entries = std::fs::read_dir(config.content_dir().join("blog-pages"))?;
+        let mut report = RefreshReport::default();
+        let mut next_scheduled = None;
+
+        let mut bundles = IndexMap::new();
 
         while let Some(entry) = entries.next().transpose()? {
             let full_path = entry.path();
+            let is_bundle = full_path.is_dir();
+
+            let slug = match is_bundle {
+                true => full_path.file_name(),
+                false => full_path.file_stem(),
+            }
+            .and_then(OsStr::to_str)
+            .map(str::to_owned)
+            .unwrap_or_default();
+
+            let (path, url) = match is_bundle {
+                true => {
+                    let index = full_path.join(BUNDLE_INDEX);
+
+                    if !index.is_file() {
+                        event!(
+                            Level::WARN,
+                            slug,
+                            "Skipping directory without an {BUNDLE_INDEX} file"
+                        );
+                        continue;
+                    }
+
+                    bundles.insert(slug.clone(), full_path.clone());
+                    (index, format!("/blog/post/{slug}/"))
+                }
+                false => (full_path.clone(), format!("/blog/post/{slug}")),
+            };
+
+            let bundle = is_bundle.then_some(full_path.as_path());
 
-            let slug = full_path
-                .file_stem()
-                .and_then(OsStr::to_str)
-                .map(str::to_owned)
-                .unwrap_or_default();
+            // A post that isn't valid UTF-8 is reported like any other broken post, rather than stopping the refresh.
+            let page = String::from_utf8(std::fs::read(&path)?)
+                .map_err(|error| {
+                    let valid = &error.as_bytes()[..error.utf8_error().valid_up_to()];
+                    let line = valid.iter().filter(|&&byte| byte == b'\n').count() + 1;
 
-            let content = std::fs::read_to_string(&full_path)?;
+                    Error::msg(format!(
+                        "`{}` contains invalid UTF-8 on line {line}",
+                        path.display()
+                    ))
+                })
+                .and_then(|content| Page::build::<PostMetadata>(&arena, &content, &url, bundle));
 
-            match Page::build::<PostMetadata>(&arena, &content) {
+            match page {
                 Ok(page) => {
                     event!(Level::INFO, slug, "Successfully imported post",);
 
+                    let diagnostics = page.diagnostics();
+
+                    for warning in diagnostics.warnings() {
+                        event!(Level::WARN, slug, warning, "Warning while importing post");
+                    }
+
+                    if !diagnostics.warnings().is_empty() {
+                        report
+                            .warnings
+                            .insert(slug.clone(), diagnostics.warnings().to_vec());
+                    }
+
+                    if !diagnostics.fallbacks().is_empty() {
+                        report
+                            .fallbacks
+                            .insert(slug.clone(), diagnostics.fallbacks().to_vec());
+                    }
+
+                    if page.is_draft() && !config.show_drafts() {
+                        event!(Level::INFO, slug, "Skipping draft");
+                        continue;
+                    }
+
+                    if let Some(published) = page.published().filter(|published| *published > now) {
+                        event!(Level::INFO, slug, %published, "Skipping scheduled post");
+
+                        next_scheduled =
+                            Some(next_scheduled.map_or(published, |next| published.min(next)));
+                        continue;
+                    }
+
+                    match self.pages.get(&slug) {
+                        None => report.added.push(slug.clone()),
+                        Some(previous) if previous.context() != page.context() => {
+                            report.changed.push(slug.clone())
+                        }
+                        Some(_) => {}
+                    }
+
                     pages.insert(slug, page);
                 }
                 Err(error) => {
@@ -58,25 +254,63 @@ impl Posts {
                         "Error importing post"
                     );
 
-                    error_count += 1;
+                    if let Some(previous) = self.pages.swap_remove(&slug) {
+                        event!(Level::WARN, slug, "Keeping previous version of post");
+
+                        pages.insert(slug.clone(), previous);
+                    }
+
+                    report.failed.insert(slug, error.to_string());
                 }
             };
         }
 
+        bundles.retain(|slug, _| pages.contains_key(slug));
+
+        report.removed = self
+            .pages
+            .keys()
+            .filter(|slug| !pages.contains_key(*slug))
+            .cloned()
+            .collect();
+
         // Look, I don't make the rules. But for some reason things need to be swapped around if we want them to be
         // ordered properly.
         let cursed_cmp_helper = |a: &Page, b: &Page| Some(b.published()?.cmp(&a.published()?));
         pages.sort_by(|_, a, _, b| cursed_cmp_helper(a, b).unwrap());
 
-        // It's important that this is done after the sorting step, since `rss_channel` expects the mapping to be in
-        // sorted order.
-        let rss = rss_channel(&pages);
+        // It's important that this is done after the sorting step, since the feeds, `tag_index`, `series_index` and
+        // `archive` expect the mapping to be in sorted order. Unlisted posts are left out of all of them.
+        let listed: IndexMap<_, _> = pages
+            .iter()
+            .filter(|(_, page)| !page.is_unlisted())
+            .map(|(slug, page)| (slug.clone(), page.clone()))
+            .collect();
 
-        *self = Posts { pages, rss };
+        let rss = feeds::rss_channel(&listed, config.site(), config.feeds().rss());
+        let atom = feeds::atom_feed(&listed, config.site(), config.feeds().atom());
+        let json = feeds::json_feed(&listed, config.site(), config.feeds().json());
+        let tags = tag_index(&listed);
+        let series = series_index(&listed);
+        let archive = archive(&listed);
 
-        match error_count {
+        link_posts(&mut pages, &listed, &series);
+
+        *self = Posts {
+            pages,
+            rss,
+            atom,
+            json,
+            tags,
+            series,
+            archive,
+            bundles,
+            next_scheduled,
+        };
+
+        match report.failed.len() {
             0 => event!(Level::INFO, "All posts imported successfully"),
-            _ => event!(
+            error_count => event!(
                 Level::WARN,
                 error_count,
                 "Unable to import {} posts",
@@ -84,7 +318,7 @@ impl Posts {
             ),
         }
 
-        Ok(())
+        Ok(report)
     }
 
     pub fn get<Q>(&self, key: &Q) -> Option<&Page>
@@ -95,53 +329,269 @@ impl Posts {
         self.pages.get(key)
     }
 
+    /// Return the directory that the post `slug` is bundled in, if it has one.
+    pub fn bundle(&self, slug: &str) -> Option<&Path> {
+        self.bundles.get(slug).map(PathBuf::as_path)
+    }
+
+    /// Iterate over the slug and directory of each post that's bundled with its assets.
+    pub fn bundles(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.bundles
+            .iter()
+            .map(|(slug, directory)| (slug.as_str(), directory.as_path()))
+    }
+
+    /// Return the asset `file` from the bundle of the post `slug`.
+    ///
+    /// This returns `None` if the post isn't bundled, or if `file` isn't a file in the bundle. The post itself isn't
+    /// considered to be an asset.
+    pub fn asset(&self, slug: &str, file: &str) -> Option<PathBuf> {
+        let file = Path::new(file);
+
+        // Anything other than plain file names (like `..`) could escape the bundle.
+        if file == Path::new(BUNDLE_INDEX)
+            || !file
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        Some(self.bundle(slug)?.join(file)).filter(|path| path.is_file())
+    }
+
     pub fn rss(&self) -> &Channel {
         &self.rss
     }
 
+    pub fn atom(&self) -> &Feed {
+        &self.atom
+    }
+
+    pub fn json(&self) -> &JsonFeed {
+        &self.json
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&str, &Page)> {
         self.pages.iter().map(|(slug, page)| (slug.as_str(), page))
     }
+
+    /// Like `Posts::iter`, but skipping any unlisted posts.
+    pub fn listed(&self) -> impl Iterator<Item = (&str, &Page)> {
+        self.iter().filter(|(_, page)| !page.is_unlisted())
+    }
+
+    /// Return how many pages it takes to list every post, with `per_page` posts on each page.
+    ///
+    /// There is always at least one page, even if there aren't any posts.
+    pub fn page_count(&self, per_page: usize) -> usize {
+        self.listed().count().div_ceil(per_page).max(1)
+    }
+
+    /// Return when the next scheduled post is due to be published, if there are any.
+    ///
+    /// Scheduled posts only become visible once posts are refreshed after this time.
+    pub fn next_scheduled(&self) -> Option<DateTime<FixedOffset>> {
+        self.next_scheduled
+    }
+
+    /// Return an iterator over every tag, in alphabetical order by slug.
+    pub fn tags(&self) -> impl Iterator<Item = (&str, &TaggedPosts)> {
+        self.tags
+            .iter()
+            .map(|(slug, tagged)| (slug.as_str(), tagged))
+    }
+
+    /// Return an iterator over every series, in alphabetical order by slug.
+    pub fn series(&self) -> impl Iterator<Item = (&str, &TaggedPosts)> {
+        self.series
+            .iter()
+            .map(|(slug, series)| (slug.as_str(), series))
+    }
+
+    /// Return the posts in the series `slug`, oldest first, if there are any.
+    pub fn in_series(&self, slug: &str) -> Option<&TaggedPosts> {
+        self.series.get(slug)
+    }
+
+    /// Return every post, grouped by when it was published.
+    pub fn archive(&self) -> &Archive {
+        &self.archive
+    }
+
+    /// Return the posts tagged with the tag `slug`, if there are any.
+    pub fn tagged(&self, slug: &str) -> Option<&TaggedPosts> {
+        self.tags.get(slug)
+    }
+}
+
+/// Group posts by their tags.
+///
+/// Posts are expected to already be sorted, and stay in the same order within each tag.
+fn tag_index(pages: &IndexMap<String, Page>) -> IndexMap<String, TaggedPosts> {
+    let mut tags = IndexMap::<String, TaggedPosts>::new();
+
+    for (slug, page) in pages {
+        for tag in page.tags() {
+            tags.entry(tag.slug().to_owned())
+                .or_insert_with(|| TaggedPosts {
+                    name: tag.name().to_owned(),
+                    posts: Vec::new(),
+                })
+                .posts
+                .push(slug.clone());
+        }
+    }
+
+    tags.sort_keys();
+    tags
+}
+
+/// Group posts by the series that they're a part of.
+///
+/// Posts are expected to already be sorted newest first, but are ordered oldest first within each series so that the
+/// first part comes first.
+fn series_index(pages: &IndexMap<String, Page>) -> IndexMap<String, TaggedPosts> {
+    let mut index = IndexMap::<String, TaggedPosts>::new();
+
+    for (slug, page) in pages.iter().rev() {
+        if let Some(series) = page.series() {
+            index
+                .entry(series.slug().to_owned())
+                .or_insert_with(|| TaggedPosts {
+                    name: series.name().to_owned(),
+                    posts: Vec::new(),
+                })
+                .posts
+                .push(slug.clone());
+        }
+    }
+
+    index.sort_keys();
+    index
+}
+
+/// Group posts by the year and month that they were published in.
+///
+/// Posts are expected to already be sorted, and stay in the same order within each month.
+fn archive(pages: &IndexMap<String, Page>) -> Archive {
+    let mut archive = Archive::new();
+
+    for (slug, page) in pages {
+        if let Some(published) = page.published() {
+            archive
+                .entry(published.year())
+                .or_default()
+                .entry(published.month())
+                .or_default()
+                .push(slug.clone());
+        }
+    }
+
+    archive
 }
 
-fn rss_channel(pages: &IndexMap<String, Page>) -> Channel {
-    let publish_date = |(_, page): (_, &Page)| page.published().as_ref().map(DateTime::to_rfc2822);
-
-    let build_item = |(slug, page): (&String, &Page)| {
-        ItemBuilder::default()
-            .author(Some("mkaylynn7@gmail.com".to_owned()))
-            .link(Some(format!("https://kaylynn.gay/blog/post/{slug}")))
-            .title(page.title())
-            .guid(Some(
-                GuidBuilder::default()
-                    .value(format!("https://kaylynn.gay/blog/post/{slug}"))
-                    .permalink(true)
-                    .build(),
-            ))
-            .description(page.description())
-            .pub_date(page.published().map(|date| date.to_rfc2822()))
-            .build()
+/// Tell each listed post about the posts published before and after it, the posts that are most related to it, and the
+/// other parts of the series that it's in.
+///
+/// Posts are expected to already be sorted.
+fn link_posts(
+    pages: &mut IndexMap<String, Page>,
+    listed: &IndexMap<String, Page>,
+    series: &IndexMap<String, TaggedPosts>,
+) {
+    for page in pages.values_mut() {
+        page.set_derived(Context::new());
+    }
+
+    let terms: Vec<_> = listed.values().map(terms).collect();
+    let tags: Vec<HashSet<_>> = listed
+        .values()
+        .map(|page| {
+            page.tags()
+                .into_iter()
+                .map(|tag| tag.slug().to_owned())
+                .collect()
+        })
+        .collect();
+
+    let link = |index: usize| -> Option<Value> {
+        let (slug, page) = listed.get_index(index)?;
+
+        Some(context! { "slug" => slug, "title" => page.title() }.into_json())
     };
 
-    let image = ImageBuilder::default()
-        .url("https://kaylynn.gay/favicon.png".to_owned())
-        .link("https://kaylynn.gay/blog".to_owned())
-        .title("Kaylynn's Blog".to_owned())
-        .description(Some("Love and be loved".to_owned()))
-        .build();
-
-    let channel = ChannelBuilder::default()
-        .title("Kaylynn's blog".to_owned())
-        .link("https://kaylynn.gay/blog".to_owned())
-        .description("Computers, cats, and eternal sleepiness".to_owned())
-        .webmaster(Some("mkaylynn7@gmail.com (Kaylynn Morgan)".to_owned()))
-        .managing_editor(Some("mkaylynn7@gmail.com (Kaylynn Morgan)".to_owned()))
-        .last_build_date(pages.first().and_then(publish_date))
-        .pub_date(pages.last().and_then(publish_date))
-        .copyright(Some("Copyright 2021-present, Kaylynn Morgan".to_owned()))
-        .image(Some(image))
-        .items(pages.iter().map(build_item).collect::<Vec<_>>())
-        .build();
-
-    channel
+    for (index, slug) in listed.keys().enumerate() {
+        let mut scores: Vec<_> = (0..listed.len())
+            .filter(|&other| other != index)
+            .map(|other| {
+                let shared_tags = tags[index].intersection(&tags[other]).count() as f64;
+                let shared_terms = terms[index].intersection(&terms[other]).count() as f64;
+                let all_terms = terms[index].union(&terms[other]).count().max(1) as f64;
+
+                (shared_tags + shared_terms / all_terms, other)
+            })
+            .filter(|&(score, _)| score >= RELATED_POST_THRESHOLD)
+            .collect();
+
+        scores.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let related: Vec<_> = scores
+            .into_iter()
+            .take(RELATED_POST_LIMIT)
+            .filter_map(|(_, other)| link(other))
+            .collect();
+
+        // Posts are sorted newest first, so the post before this one in publishing order comes after it here.
+        let mut derived = context! {
+            "previous" => link(index + 1),
+            "next" => index.checked_sub(1).and_then(link),
+            "related" => related,
+        };
+
+        let parts = listed[index]
+            .series()
+            .and_then(|current| series.get(current.slug()))
+            .map(TaggedPosts::posts)
+            .unwrap_or_default();
+
+        if let Some(part) = parts.iter().position(|part| part == slug) {
+            let parts: Vec<_> = parts
+                .iter()
+                .filter_map(|part| listed.get_index_of(part).and_then(link))
+                .collect();
+
+            derived.insert("series_part", &(part + 1));
+            derived.insert("series_parts", &parts);
+        }
+
+        if let Some(page) = pages.get_mut(slug) {
+            page.set_derived(derived);
+        }
+    }
+}
+
+/// Return the distinct words used in the title and content of `page`, ignoring any markup.
+fn terms(page: &Page) -> HashSet<String> {
+    let content = page.content().unwrap_or_default();
+    let mut text = page.title().unwrap_or_default();
+    let mut in_tag = false;
+
+    for character in content.chars() {
+        match character {
+            '<' => in_tag = true,
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(character),
+            _ => {}
+        }
+    }
+
+    text.split(|character: char| !character.is_alphabetic())
+        .filter(|word| word.chars().count() >= 4)
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .collect()
 }
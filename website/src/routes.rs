@@ -10,21 +10,29 @@ use crate::{
     context,
     error::{HttpError, HttpResult, IntoHttpResult},
     page::Page,
+    posts::RefreshReport,
     shutdown::Shutdown,
+    sitemap,
     state::State,
 };
+use atom_syndication::WriteConfig;
 use axum::{
-    body::Bytes,
+    body::{boxed, Body, Bytes},
     extract::{FromRequestParts, Path},
-    http::{request::Parts, HeaderValue, StatusCode},
+    http::{request::Parts, HeaderValue, Request, StatusCode},
     response::{Html, IntoResponse, Response},
+    Json,
 };
+use chrono::Month;
 use hex::ToHex;
 use hmac::{Hmac, Mac, NewMac};
 use hyper::header;
+use serde::Serialize;
 use serde_json::Value;
 use sha2::Sha256;
 use tera::Context;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
 
 #[derive(Debug, Clone)]
 pub struct StaticPage {
@@ -62,46 +70,324 @@ where
     }
 }
 
+/// Build the context used to list `page` on pages like `/blog`.
+fn post_summary(slug: &str, page: &Page) -> Option<Value> {
+    let context = page.context();
+
+    let mut summary = Context::new();
+    summary.insert("slug", slug);
+    summary.insert("title", context.get("title")?);
+    summary.insert("published", context.get("published")?);
+    summary.insert("tags", context.get("tags")?);
+    summary.insert("draft", context.get("draft")?);
+    summary.insert("summary_html", context.get("summary_html")?);
+    summary.insert("summary_text", context.get("summary_text")?);
+    summary.insert("word_count", context.get("word_count")?);
+    summary.insert("reading_time", context.get("reading_time")?);
+
+    Some(summary.into_json())
+}
+
+/// Where a page of `/blog` sits relative to the others.
+#[derive(Debug, Serialize)]
+struct Pagination {
+    current: usize,
+    total: usize,
+    previous: Option<String>,
+    next: Option<String>,
+}
+
+impl Pagination {
+    fn new(current: usize, total: usize) -> Self {
+        Self {
+            current,
+            total,
+            previous: (current > 1).then(|| page_url(current - 1)),
+            next: (current < total).then(|| page_url(current + 1)),
+        }
+    }
+}
+
+/// Return the URL of page `number` of `/blog`.
+pub fn page_url(number: usize) -> String {
+    match number {
+        1 => "/blog".to_owned(),
+        _ => format!("/blog/page/{number}"),
+    }
+}
+
 pub async fn post_list(state: State) -> Response {
-    let context_for = |slug: &str, context: &Context| -> Option<Value> {
-        let mut new = Context::new();
-        new.insert("slug", slug);
-        new.insert("title", context.get("title")?);
-        new.insert("published", context.get("published")?);
+    render_post_list(&state, 1)
+}
+
+pub async fn paged_post_list(Path(number): Path<String>, state: State) -> Response {
+    match number.parse() {
+        Ok(number) => render_post_list(&state, number),
+        Err(_) => HttpError::msg("Page not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response(),
+    }
+}
+
+/// Render page `number` of `/blog`, which starts from 1.
+fn render_post_list(state: &State, number: usize) -> Response {
+    let per_page = state.config().posts_per_page();
+    let posts = state.posts();
+    let total = posts.page_count(per_page);
+
+    if number == 0 || number > total {
+        return HttpError::msg("Page not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response();
+    }
+
+    let summaries: Vec<_> = posts
+        .listed()
+        .skip((number - 1) * per_page)
+        .take(per_page)
+        .filter_map(|(slug, page)| post_summary(slug, page))
+        .collect();
+
+    let page = Page::new(
+        "post-list",
+        context! {
+            "title" => state.config().site().title(),
+            "posts" => summaries,
+            "pagination" => Pagination::new(number, total),
+            "years" => posts.archive().keys().collect::<Vec<_>>(),
+        },
+    );
+
+    page.render(&state.engine())
+        .into_http_result()
+        .into_response()
+}
+
+pub async fn tag_list(state: State) -> Response {
+    let tags: Vec<_> = state
+        .posts()
+        .tags()
+        .map(|(slug, tagged)| {
+            context! {
+                "slug" => slug,
+                "name" => tagged.name(),
+                "count" => tagged.posts().len(),
+            }
+            .into_json()
+        })
+        .collect();
+
+    let page = Page::new(
+        "tag-list",
+        context! {
+            "title" => "Tags",
+            "tags" => tags,
+        },
+    );
+
+    page.render(&state.engine())
+        .into_http_result()
+        .into_response()
+}
 
-        Some(new.into_json())
+pub async fn tagged_post_list(Path(tag): Path<String>, state: State) -> Response {
+    let posts = state.posts();
+
+    let tagged = match posts.tagged(&tag) {
+        Some(tagged) => tagged,
+        None => {
+            return HttpError::msg("Tag not found!")
+                .with_status(StatusCode::NOT_FOUND)
+                .into_response()
+        }
     };
 
-    let posts: Vec<_> = state
+    let summaries: Vec<_> = tagged
         .posts()
         .iter()
-        .filter_map(|(slug, page)| context_for(slug, page.context()))
+        .filter_map(|slug| post_summary(slug, posts.get(slug)?))
         .collect();
 
+    let heading = format!("Posts tagged \"{}\"", tagged.name());
     let page = Page::new(
         "post-list",
         context! {
-            "title" => "Kaylynn's blog",
-            "posts" => posts,
+            "title" => heading,
+            "heading" => heading,
+            "posts" => summaries,
         },
     );
 
-    page.render(state.engine())
+    page.render(&state.engine())
         .into_http_result()
         .into_response()
 }
 
+pub async fn series(Path(slug): Path<String>, state: State) -> Response {
+    let posts = state.posts();
+
+    let Some(series) = posts.in_series(&slug) else {
+        return HttpError::msg("Series not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response();
+    };
+
+    let summaries: Vec<_> = series
+        .posts()
+        .iter()
+        .filter_map(|slug| post_summary(slug, posts.get(slug)?))
+        .collect();
+
+    let page = Page::new(
+        "series",
+        context! {
+            "title" => series.name(),
+            "slug" => slug,
+            "posts" => summaries,
+        },
+    );
+
+    page.render(&state.engine())
+        .into_http_result()
+        .into_response()
+}
+
+pub async fn year_archive(Path(year): Path<String>, state: State) -> Response {
+    render_archive(&state, &year, None)
+}
+
+pub async fn month_archive(Path((year, month)): Path<(String, String)>, state: State) -> Response {
+    render_archive(&state, &year, Some(&month))
+}
+
+/// Render the posts published in `year`, optionally narrowed down to a single `month`.
+fn render_archive(state: &State, year: &str, month: Option<&str>) -> Response {
+    let not_found = || {
+        HttpError::msg("Nothing was published then!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response()
+    };
+
+    let posts = state.posts();
+
+    let Some((year, months)) = year
+        .parse::<i32>()
+        .ok()
+        .and_then(|year| posts.archive().get_key_value(&year))
+    else {
+        return not_found();
+    };
+
+    let months: Vec<_> = match month {
+        None => months.iter().collect(),
+        Some(month) => match month
+            .parse::<u32>()
+            .ok()
+            .and_then(|month| months.get_key_value(&month))
+        {
+            Some(entry) => vec![entry],
+            None => return not_found(),
+        },
+    };
+
+    let month_contexts: Vec<_> = months
+        .iter()
+        .map(|(&month, slugs)| {
+            let summaries: Vec<_> = slugs
+                .iter()
+                .filter_map(|slug| post_summary(slug, posts.get(slug)?))
+                .collect();
+
+            context! {
+                "number" => month,
+                "name" => month_name(month),
+                "url" => format!("/blog/{year}/{month:02}"),
+                "posts" => summaries,
+            }
+            .into_json()
+        })
+        .collect();
+
+    let heading = match (month, &months[..]) {
+        (Some(_), [(&number, _)]) => format!("Posts from {} {year}", month_name(number)),
+        _ => format!("Posts from {year}"),
+    };
+
+    let page = Page::new(
+        "archive",
+        context! {
+            "title" => heading,
+            "heading" => heading,
+            "years" => vec![context! {
+                "year" => year,
+                "months" => month_contexts,
+            }
+            .into_json()],
+        },
+    );
+
+    page.render(&state.engine())
+        .into_http_result()
+        .into_response()
+}
+
+fn month_name(month: u32) -> &'static str {
+    u8::try_from(month)
+        .ok()
+        .and_then(|month| Month::try_from(month).ok())
+        .map(|month| month.name())
+        .unwrap_or("Unknown")
+}
+
 pub async fn post(Path(slug): Path<String>, state: State) -> Response {
     state
         .posts()
         .get(&slug)
         .ok_or(HttpError::msg("Blog post not found!").with_status(StatusCode::NOT_FOUND))
-        .and_then(|page| page.render(state.engine()).into_http_result())
+        .and_then(|page| page.render(&state.engine()).into_http_result())
         .into_response()
 }
 
+/// Serve a resized image from the image cache.
+pub async fn image(Path(file): Path<String>, state: State, request: Request<Body>) -> Response {
+    let path = state.config().image_cache_dir().join(&file);
+
+    // Only plain file names are allowed, so that nothing outside of the cache can be served.
+    if FsPath::new(&file).file_name() != Some(file.as_ref()) || !path.is_file() {
+        return HttpError::msg("Image not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response();
+    }
+
+    serve_file(path, request).await
+}
+
+/// Serve an asset from the bundle of the post `slug`.
+pub async fn post_asset(
+    Path((slug, file)): Path<(String, String)>,
+    state: State,
+    request: Request<Body>,
+) -> Response {
+    let Some(path) = state.posts().asset(&slug, file.trim_start_matches('/')) else {
+        return HttpError::msg("File not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response();
+    };
+
+    serve_file(path, request).await
+}
+
+async fn serve_file(path: PathBuf, request: Request<Body>) -> Response {
+    // Serving files never fails, errors are turned into responses.
+    match ServeFile::new(path).oneshot(request).await {
+        Ok(response) => response.map(boxed).into_response(),
+        Err(error) => match error {},
+    }
+}
+
 pub async fn rss_feed(state: State) -> Response {
-    let rss = state.posts().rss();
+    let posts = state.posts();
+    let rss = posts.rss();
     let headers = [(
         header::CONTENT_TYPE,
         HeaderValue::from_static("application/rss+xml; charset=UTF-8"),
@@ -116,6 +402,82 @@ pub async fn rss_feed(state: State) -> Response {
     (headers, xml).into_response()
 }
 
+pub async fn atom_feed(state: State) -> Response {
+    let posts = state.posts();
+    let atom = posts.atom();
+    let headers = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/atom+xml; charset=UTF-8"),
+    )];
+
+    // Panic safety: Same as above.
+    let config = WriteConfig {
+        indent_size: Some(2),
+        ..Default::default()
+    };
+    let buffer = atom.write_with_config(Vec::new(), config).unwrap();
+    let xml = String::from_utf8(buffer).unwrap();
+
+    (headers, xml).into_response()
+}
+
+pub async fn json_feed(state: State) -> Response {
+    let posts = state.posts();
+    let headers = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/feed+json; charset=UTF-8"),
+    )];
+
+    (headers, Json(posts.json())).into_response()
+}
+
+pub async fn sitemap(state: State) -> Response {
+    let headers = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/xml; charset=UTF-8"),
+    )];
+
+    let xml = sitemap::sitemap(state.config().site(), &state.posts());
+
+    (headers, xml).into_response()
+}
+
+pub async fn robots(state: State) -> Response {
+    let headers = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=UTF-8"),
+    )];
+
+    let text = sitemap::robots(state.config().site(), state.config().robots());
+
+    (headers, text).into_response()
+}
+
+pub async fn highlight_stylesheet(state: State) -> Response {
+    stylesheet(state.themes().combined().to_owned())
+}
+
+pub async fn theme_stylesheet(Path(file): Path<String>, state: State) -> Response {
+    let themes = state.themes();
+
+    // Route parameters have to cover an entire path segment, so the extension is checked here instead.
+    match file.strip_suffix(".css").and_then(|name| themes.get(name)) {
+        Some(css) => stylesheet(css.to_owned()),
+        None => HttpError::msg("Theme not found!")
+            .with_status(StatusCode::NOT_FOUND)
+            .into_response(),
+    }
+}
+
+fn stylesheet(css: String) -> Response {
+    let headers = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/css; charset=UTF-8"),
+    )];
+
+    (headers, css).into_response()
+}
+
 trait MacExt {
     fn with_data(self, data: &[u8]) -> Self;
 }
@@ -162,12 +524,8 @@ where
     }
 }
 
-pub async fn deploy(
-    shutdown: Shutdown,
-    request_secret: Secret,
-    state: State,
-    body: Bytes,
-) -> HttpResult<()> {
+/// Check that `body` was signed with the configured webhook secret, and parse it as JSON.
+fn verify_webhook(request_secret: &Secret, state: &State, body: &Bytes) -> HttpResult<Value> {
     let secret = state
         .config()
         .webhook_secret()
@@ -186,9 +544,19 @@ pub async fn deploy(
     }
 
     let raw = String::from_utf8_lossy(body.as_ref());
-    let payload = Value::from_str(&raw).map_err(|_| {
+
+    Value::from_str(&raw).map_err(|_| {
         HttpError::msg("Invalid JSON in request body").with_status(StatusCode::BAD_REQUEST)
-    })?;
+    })
+}
+
+pub async fn deploy(
+    shutdown: Shutdown,
+    request_secret: Secret,
+    state: State,
+    body: Bytes,
+) -> HttpResult<()> {
+    let payload = verify_webhook(&request_secret, &state, &body)?;
 
     // We only want to trigger a shutdown once the actions run is completed and a new image is present on Docker Hub
     if payload["action"] == "completed" {
@@ -197,3 +565,38 @@ pub async fn deploy(
 
     Ok(())
 }
+
+#[derive(Debug, Serialize)]
+pub struct ContentDeploySummary {
+    #[serde(flatten)]
+    posts: RefreshReport,
+    template_error: Option<String>,
+}
+
+/// Refresh posts and templates in-process, without restarting the server.
+///
+/// This is useful for content-only changes, since it doesn't drop any connections.
+pub async fn deploy_content(
+    request_secret: Secret,
+    state: State,
+    body: Bytes,
+) -> HttpResult<Json<ContentDeploySummary>> {
+    verify_webhook(&request_secret, &state, &body)?;
+
+    // Importing posts can take a while (resizing images, for example), so it's kept off the async runtime.
+    tokio::task::spawn_blocking(move || {
+        // Shortcodes are expanded when posts are imported, so reloading them brings in any new posts too.
+        let posts = state.reload_shortcodes()?;
+        let template_error = state
+            .reload_templates()
+            .err()
+            .map(|error| error.to_string());
+
+        Ok(Json(ContentDeploySummary {
+            posts,
+            template_error,
+        }))
+    })
+    .await
+    .map_err(|error| HttpError::msg(error.to_string()))?
+}
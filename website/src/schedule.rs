@@ -0,0 +1,51 @@
+use chrono::Local;
+use std::time::Duration;
+use tracing::{event, Level};
+
+use crate::{error::Error, state::State};
+
+/// The shortest amount of time to wait between checks, so that a failing refresh doesn't spin.
+const MIN_WAIT: Duration = Duration::from_secs(1);
+
+/// The longest amount of time to wait between checks. Posts can be reloaded at any time (which might schedule something
+/// sooner than we were expecting) so it's important to check back every so often. This is also how late a post can be
+/// published if it's scheduled while we're waiting.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Refresh posts whenever a scheduled post is due to be published.
+///
+/// This future never resolves, so it should be spawned as a task.
+pub async fn publish_scheduled(state: State) {
+    loop {
+        let now = Local::now();
+        let next_scheduled = state.posts().next_scheduled();
+
+        if next_scheduled.is_some_and(|next| next <= now) {
+            event!(Level::INFO, "Publishing scheduled posts");
+
+            // Importing posts can take a while (resizing images, for example), so it's kept off the async runtime.
+            let reloading = state.clone();
+            let result = tokio::task::spawn_blocking(move || reloading.reload_posts().map(drop))
+                .await
+                .map_err(|error| Error::msg(error.to_string()))
+                .and_then(|result| result);
+
+            if let Err(error) = result {
+                event!(
+                    Level::ERROR,
+                    error = &error as &dyn std::error::Error,
+                    "Unable to publish scheduled posts"
+                );
+            }
+        }
+
+        let wait = state
+            .posts()
+            .next_scheduled()
+            .and_then(|next| (next.with_timezone(&Local) - Local::now()).to_std().ok())
+            .unwrap_or(MAX_WAIT)
+            .clamp(MIN_WAIT, MAX_WAIT);
+
+        tokio::time::sleep(wait).await;
+    }
+}
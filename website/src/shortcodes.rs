@@ -0,0 +1,390 @@
+use serde::Deserialize;
+use std::{collections::HashSet, error::Error as _, path::Path};
+use tera::{Context, Tera};
+
+use crate::{
+    error::{Error, Result},
+    markdown, SHORTCODES,
+};
+
+const OPEN: &str = "{{<";
+const CLOSE: &str = ">}}";
+const PLACEHOLDER: &str = "<!-- shortcode ";
+const TEMPLATE_EXTENSION: &str = ".html.tera";
+
+/// A single argument to a shortcode, wrapped up so that it can be parsed as TOML.
+#[derive(Debug, Deserialize)]
+struct Argument {
+    value: toml::Value,
+}
+
+/// The templates that shortcodes expand into.
+#[derive(Debug, Default)]
+pub struct Shortcodes {
+    tera: Tera,
+}
+
+impl Shortcodes {
+    /// Load every template in `directory`. The directory doesn't need to exist.
+    ///
+    /// Unlike page templates, arguments are escaped automatically, since they come straight from posts.
+    pub fn load(directory: &Path) -> Result<Self> {
+        let mut tera = match directory.is_dir() {
+            true => Tera::new(
+                &directory
+                    .join(format!("*{TEMPLATE_EXTENSION}"))
+                    .to_string_lossy(),
+            )?,
+            false => Tera::default(),
+        };
+
+        tera.autoescape_on(vec![TEMPLATE_EXTENSION]);
+
+        Ok(Self { tera })
+    }
+
+    /// Render the shortcode called `name`, returning a description of the problem if it fails.
+    fn render(&self, name: &str, context: &Context) -> std::result::Result<String, String> {
+        let template_name = format!("{name}{TEMPLATE_EXTENSION}");
+
+        if !self
+            .tera
+            .get_template_names()
+            .any(|name| name == template_name)
+        {
+            return Err(format!("there's no shortcode called `{name}`"));
+        }
+
+        self.tera.render(&template_name, context).map_err(|error| {
+            // Tera's errors only describe what went wrong in their source, which is easy to miss.
+            let mut message = error.to_string();
+            let mut source = error.source();
+
+            while let Some(error) = source {
+                message.push_str(&format!(": {error}"));
+                source = error.source();
+            }
+
+            message
+        })
+    }
+}
+
+/// Expand the shortcodes in Markdown source, returning the marked-up source and the HTML for each shortcode.
+///
+/// A shortcode looks like `{{< figure src="cat.webp" width=300 >}}`, and is rendered with the template of the same name
+/// from `templates/shortcodes`. Arguments are TOML values, and are available to the template as variables. Each
+/// shortcode is replaced with an HTML comment, which `to_html` swaps for the rendered HTML once the source is parsed,
+/// so that the HTML isn't mangled by the Markdown parser. Shortcodes in front matter and code are left untouched, and
+/// no lines are added or removed.
+///
+/// This function returns an error if a shortcode is malformed, doesn't exist, or fails to render.
+pub fn mark(source: &str) -> Result<(String, Vec<String>)> {
+    let shortcodes = SHORTCODES.read().unwrap();
+    let mut output = String::with_capacity(source.len());
+    let mut rendered = Vec::new();
+
+    for (index, (line, is_text)) in markdown::source_lines(source).enumerate() {
+        if !is_text {
+            output.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+
+        while let Some(start) = rest.find(['`', '{']) {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if !rest.starts_with(OPEN) {
+                let length = match rest.starts_with('`') {
+                    true => markdown::code_span_length(rest),
+                    false => 1,
+                };
+
+                output.push_str(&rest[..length]);
+                rest = &rest[length..];
+                continue;
+            }
+
+            let (html, length) = parse_call(rest)
+                .and_then(|(name, context, length)| {
+                    Ok((shortcodes.render(name, &context)?, length))
+                })
+                .map_err(|error| Error::msg(format!("shortcode on line {}: {error}", index + 1)))?;
+
+            output.push_str(&format!("{PLACEHOLDER}{} -->", rendered.len()));
+            rendered.push(html.trim_end().to_owned());
+            rest = &rest[length..];
+        }
+
+        output.push_str(rest);
+    }
+
+    Ok((output, rendered))
+}
+
+/// Replace the comments left behind by `mark` in `html` with the shortcodes they stand for.
+///
+/// This function returns `None` if `html` doesn't contain any of these comments.
+pub fn to_html(html: &str, rendered: &[String]) -> Option<String> {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut replaced = false;
+
+    while let Some(start) = rest.find(PLACEHOLDER) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let shortcode = rest[PLACEHOLDER.len()..]
+            .split_once(" -->")
+            .and_then(|(index, _)| rendered.get(index.parse::<usize>().ok()?));
+
+        match shortcode {
+            Some(shortcode) => {
+                output.push_str(shortcode);
+                rest = &rest[rest.find(" -->").unwrap() + " -->".len()..];
+                replaced = true;
+            }
+            None => {
+                output.push_str(PLACEHOLDER);
+                rest = &rest[PLACEHOLDER.len()..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+
+    replaced.then_some(output)
+}
+
+/// Parse the shortcode at the start of `text`, returning its name, its arguments, and its total length.
+fn parse_call(text: &str) -> std::result::Result<(&str, Context, usize), String> {
+    let mut rest = text[OPEN.len()..].trim_start();
+    let name = identifier(rest);
+
+    if name.is_empty() {
+        return Err("the shortcode doesn't have a name".to_owned());
+    }
+
+    rest = &rest[name.len()..];
+
+    let mut context = Context::new();
+    let mut keys = HashSet::new();
+
+    loop {
+        let trimmed = rest.trim_start();
+
+        if let Some(after) = trimmed.strip_prefix(CLOSE) {
+            return Ok((name, context, text.len() - after.len()));
+        }
+
+        if trimmed.is_empty() {
+            return Err(format!(
+                "`{name}` isn't closed with `{CLOSE}` on the same line"
+            ));
+        }
+
+        let key = identifier(trimmed);
+
+        if key.is_empty() || trimmed.len() == rest.len() {
+            return Err(format!(
+                "expected an argument to `{name}`, found `{}`",
+                trimmed.trim_end()
+            ));
+        }
+
+        let Some(value) = trimmed[key.len()..].strip_prefix('=') else {
+            return Err(format!("the argument `{key}` doesn't have a value"));
+        };
+
+        let length = value_length(value);
+        let argument = toml::from_str::<Argument>(&format!("value = {}", &value[..length]))
+            .map_err(|_| format!("the value of `{key}` isn't valid: `{}`", &value[..length]))?;
+
+        if !keys.insert(key) {
+            return Err(format!("the argument `{key}` is given more than once"));
+        }
+
+        context.insert(key, &argument.value);
+        rest = &value[length..];
+    }
+}
+
+/// Return the identifier at the start of `text`, which is empty if there isn't one.
+fn identifier(text: &str) -> &str {
+    let length = text
+        .find(|character: char| !(character.is_ascii_alphanumeric() || character == '_'))
+        .unwrap_or(text.len());
+
+    &text[..length]
+}
+
+/// Return the length of the argument value at the start of `text`.
+///
+/// Quoted strings run until the closing quote, and anything else runs until whitespace or the end of the shortcode.
+fn value_length(text: &str) -> usize {
+    if let Some(string) = text.strip_prefix('"') {
+        let mut escaped = false;
+
+        for (index, character) in string.char_indices() {
+            match character {
+                '"' if !escaped => return index + 2,
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+
+        return text.len();
+    }
+
+    text.find(char::is_whitespace)
+        .into_iter()
+        .chain(text.find(CLOSE))
+        .min()
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load the shortcodes that come with the site.
+    fn load_site_shortcodes() {
+        markdown::load_shortcodes(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("content/templates/shortcodes"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn parse_call_arguments() {
+        let text = "{{< figure src=\"cat.webp\" width=300 lazy=true sizes=[1,2] >}} and more";
+        let (name, context, length) = parse_call(text).unwrap();
+
+        assert_eq!(name, "figure");
+        assert_eq!(&text[length..], " and more");
+        assert_eq!(context.get("src"), Some(&"cat.webp".into()));
+        assert_eq!(context.get("width"), Some(&300.into()));
+        assert_eq!(context.get("lazy"), Some(&true.into()));
+        assert_eq!(context.get("sizes"), Some(&vec![1, 2].into()));
+    }
+
+    #[test]
+    fn parse_call_without_spaces() {
+        let (name, context, length) = parse_call("{{<sidenote text=\"hi\">}}").unwrap();
+
+        assert_eq!(name, "sidenote");
+        assert_eq!(length, "{{<sidenote text=\"hi\">}}".len());
+        assert_eq!(context.get("text"), Some(&"hi".into()));
+    }
+
+    #[test]
+    fn parse_call_quoted_strings() {
+        let text = r#"{{< sidenote text="a \"quoted\" >}} \\" >}}"#;
+        let (_, context, length) = parse_call(text).unwrap();
+
+        assert_eq!(length, text.len());
+        assert_eq!(context.get("text"), Some(&r#"a "quoted" >}} \"#.into()));
+    }
+
+    #[test]
+    fn parse_call_errors() {
+        for (text, error) in [
+            ("{{< >}}", "the shortcode doesn't have a name"),
+            (
+                "{{< figure src=\"cat.webp\"",
+                "`figure` isn't closed with `>}}` on the same line",
+            ),
+            (
+                "{{< figure \"cat.webp\" >}}",
+                "expected an argument to `figure`, found `\"cat.webp\" >}}`",
+            ),
+            (
+                "{{< figure src >}}",
+                "the argument `src` doesn't have a value",
+            ),
+            (
+                "{{< figure src=cat.webp >}}",
+                "the value of `src` isn't valid: `cat.webp`",
+            ),
+            (
+                "{{< figure src=\"a\" src=\"b\" >}}",
+                "the argument `src` is given more than once",
+            ),
+            (
+                "{{< figure src=\"a\"alt=\"b\" >}}",
+                "expected an argument to `figure`, found `alt=\"b\" >}}`",
+            ),
+        ] {
+            assert_eq!(parse_call(text).err().as_deref(), Some(error), "{text}");
+        }
+    }
+
+    #[test]
+    fn mark_replaces_shortcodes() {
+        load_site_shortcodes();
+
+        let (marked, rendered) =
+            mark("A {{< sidenote text=\"<b>\" >}} and {{< sidenote text=\"b\" >}}.\n").unwrap();
+
+        assert_eq!(marked, "A <!-- shortcode 0 --> and <!-- shortcode 1 -->.\n");
+        assert_eq!(
+            rendered,
+            [
+                "<span class=\"sidenote\">&lt;b&gt;</span>",
+                "<span class=\"sidenote\">b</span>"
+            ]
+        );
+    }
+
+    #[test]
+    fn mark_leaves_code_alone() {
+        for source in [
+            "Write `{{< nope >}}` to use a shortcode.\n",
+            "Or ``{{< nope >}} ` {{< nope >}}`` even.\n",
+            "```\n{{< nope >}}\n```\n",
+            "---\ntitle = \"{{< nope >}}\"\n---\n",
+            "Ordinary {{ braces }} and { things }.\n",
+        ] {
+            let (marked, rendered) = mark(source).unwrap();
+
+            assert_eq!(marked, source);
+            assert!(rendered.is_empty());
+        }
+    }
+
+    #[test]
+    fn mark_reports_line_numbers() {
+        load_site_shortcodes();
+
+        let error = mark("---\ntitle = \"A\"\n---\n\n{{< nope >}}\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "shortcode on line 5: there's no shortcode called `nope`"
+        );
+
+        let error = mark("Text\n\n{{< figure >}}\n").unwrap_err().to_string();
+        assert!(error.starts_with("shortcode on line 3: "), "{error}");
+        assert!(error.contains("`src` is required"), "{error}");
+    }
+
+    #[test]
+    fn to_html_replaces_placeholders() {
+        let rendered = ["<b>zero</b>".to_owned(), "<i>one</i>".to_owned()];
+
+        assert_eq!(
+            to_html(
+                "<p><!-- shortcode 1 --> <!-- shortcode 0 --></p>",
+                &rendered
+            )
+            .as_deref(),
+            Some("<p><i>one</i> <b>zero</b></p>")
+        );
+        assert_eq!(
+            to_html("<!-- shortcode 2 --> <!-- shortcode x -->", &rendered),
+            None
+        );
+        assert_eq!(to_html("<p>Nothing here</p>", &rendered), None);
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, FixedOffset};
+use std::fmt::Write;
+
+use crate::{
+    page::Page,
+    posts::Posts,
+    state::{RobotsConfig, SiteConfig},
+};
+
+/// Routes that are always present, regardless of which posts exist.
+const STATIC_ROUTES: [&str; 4] = ["/", "/about", "/blog", "/blog/tags"];
+
+/// Build a sitemap, as described by <https://www.sitemaps.org/protocol.html>.
+///
+/// Drafts and unlisted posts are left out, since they aren't meant to be found by crawlers.
+pub fn sitemap(site: &SiteConfig, posts: &Posts) -> String {
+    let visible: Vec<_> = posts
+        .listed()
+        .filter(|(_, page)| !page.is_draft())
+        .collect();
+
+    let mut urls: Vec<(String, Option<DateTime<FixedOffset>>)> = STATIC_ROUTES
+        .iter()
+        .map(|route| (site.url(route), None))
+        .collect();
+
+    for (slug, page) in &visible {
+        urls.push((site.url(&format!("/blog/post/{slug}")), last_modified(page)));
+    }
+
+    let groups = posts
+        .tags()
+        .map(|(slug, tagged)| (format!("/blog/tag/{slug}"), tagged))
+        .chain(
+            posts
+                .series()
+                .map(|(slug, series)| (format!("/blog/series/{slug}"), series)),
+        );
+
+    for (route, group) in groups {
+        let newest = visible
+            .iter()
+            .filter(|(post, _)| group.posts().iter().any(|grouped| grouped == post))
+            .filter_map(|(_, page)| last_modified(page))
+            .max();
+
+        if newest.is_some() {
+            urls.push((site.url(&route), newest));
+        }
+    }
+
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+        "\n",
+    ));
+
+    // Writing to a `String` can't fail.
+    for (url, modified) in urls {
+        writeln!(xml, "  <url>").unwrap();
+        writeln!(xml, "    <loc>{}</loc>", escape(&url)).unwrap();
+
+        if let Some(modified) = modified {
+            writeln!(xml, "    <lastmod>{}</lastmod>", modified.to_rfc3339()).unwrap();
+        }
+
+        writeln!(xml, "  </url>").unwrap();
+    }
+
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Build a `robots.txt` that points crawlers to the sitemap.
+pub fn robots(site: &SiteConfig, robots: &RobotsConfig) -> String {
+    let mut text = String::from("User-agent: *\n");
+
+    // An empty `Disallow` means that everything may be crawled.
+    match robots.disallow() {
+        [] => text.push_str("Disallow:\n"),
+        paths => {
+            for path in paths {
+                writeln!(text, "Disallow: {path}").unwrap();
+            }
+        }
+    }
+
+    writeln!(text, "\nSitemap: {}", site.url("/sitemap.xml")).unwrap();
+    text
+}
+
+/// Return when `page` was last modified, falling back to when it was published.
+fn last_modified(page: &Page) -> Option<DateTime<FixedOffset>> {
+    page.updated().or_else(|| page.published())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
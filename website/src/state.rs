@@ -1,29 +1,38 @@
 use crate::{
+    context,
     error::Result,
-    posts::Posts,
-    templates::{self, Engine},
+    markdown,
+    posts::{Posts, RefreshReport},
+    templates::Engine,
+    themes::Themes,
 };
 use axum::{
     extract::{Extension, FromRequestParts},
     http::request::Parts,
 };
 use figment::{
-    providers::{Env, Format, Toml},
+    providers::{Env, Format, Serialized, Toml},
     Figment,
 };
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::{
     net::IpAddr,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex, RwLock},
 };
-use tera::Tera;
+use tera::Context;
+use url::Url;
 
 #[derive(Debug)]
 pub struct StateInner {
     config: Config,
-    engine: Engine,
-    posts: Posts,
+    engine: RwLock<Arc<Engine>>,
+    posts: RwLock<Arc<Posts>>,
+    themes: RwLock<Arc<Themes>>,
+    /// Held while posts are being reloaded, so that concurrent reloads can't overwrite each other.
+    reloading: Mutex<()>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,12 +40,31 @@ pub struct Config {
     #[serde(default)]
     webhook_secret: Option<String>,
     content_dir: PathBuf,
+    #[serde(default)]
+    show_drafts: bool,
+    #[serde(default = "default_posts_per_page")]
+    posts_per_page: NonZeroUsize,
     host: HostConfig,
+    #[serde(default)]
+    feeds: FeedsConfig,
+    #[serde(default)]
+    site: SiteConfig,
+    #[serde(default)]
+    robots: RobotsConfig,
+    #[serde(default)]
+    markdown: MarkdownConfig,
+    #[serde(default)]
+    highlight: HighlightConfig,
+    #[serde(default)]
+    images: ImagesConfig,
 }
 
 impl Config {
     pub fn figment() -> Figment {
         Figment::new()
+            // Drafts are useful to see when working on the site, but shouldn't be visible to everyone else.
+            .merge(Serialized::default("show_drafts", true).profile("debug"))
+            .merge(Serialized::default("show_drafts", false).profile("release"))
             .merge(Toml::file("App.toml").nested())
             .merge(Env::prefixed("WOEBLOG_"))
     }
@@ -49,9 +77,51 @@ impl Config {
         &self.content_dir
     }
 
+    pub fn show_drafts(&self) -> bool {
+        self.show_drafts
+    }
+
+    /// The number of posts shown on each page of `/blog`.
+    pub fn posts_per_page(&self) -> usize {
+        self.posts_per_page.get()
+    }
+
     pub fn host(&self) -> &HostConfig {
         &self.host
     }
+
+    pub fn feeds(&self) -> &FeedsConfig {
+        &self.feeds
+    }
+
+    pub fn site(&self) -> &SiteConfig {
+        &self.site
+    }
+
+    pub fn robots(&self) -> &RobotsConfig {
+        &self.robots
+    }
+
+    pub fn markdown(&self) -> &MarkdownConfig {
+        &self.markdown
+    }
+
+    pub fn highlight(&self) -> &HighlightConfig {
+        &self.highlight
+    }
+
+    pub fn images(&self) -> &ImagesConfig {
+        &self.images
+    }
+
+    /// The directory that resized images are written to.
+    pub fn image_cache_dir(&self) -> PathBuf {
+        self.content_dir.join(&self.images.cache_dir)
+    }
+}
+
+fn default_posts_per_page() -> NonZeroUsize {
+    NonZeroUsize::new(10).unwrap()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,30 +140,377 @@ impl HostConfig {
     }
 }
 
+/// Details about the site itself, used when building feeds and absolute links.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteConfig {
+    base_url: Url,
+    title: String,
+    description: String,
+    copyright: String,
+    logo: String,
+    author: AuthorConfig,
+}
+
+impl SiteConfig {
+    /// The URL that the site is hosted at.
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn copyright(&self) -> &str {
+        &self.copyright
+    }
+
+    /// The absolute URL of the site's logo.
+    pub fn logo(&self) -> String {
+        self.url(&self.logo)
+    }
+
+    pub fn author(&self) -> &AuthorConfig {
+        &self.author
+    }
+
+    /// Resolve `path` against the base URL, returning an absolute URL.
+    pub fn url(&self, path: &str) -> String {
+        self.base_url
+            .join(path)
+            .map(String::from)
+            .unwrap_or_else(|_| path.to_owned())
+    }
+
+    /// Return the global template context that describes the site.
+    pub fn context(&self) -> Context {
+        context! {
+            "site" => context! {
+                "base_url" => self.base_url.as_str(),
+                "title" => self.title,
+                "description" => self.description,
+                "copyright" => self.copyright,
+                "logo" => self.logo(),
+                "author" => self.author,
+            }
+            .into_json(),
+        }
+    }
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            base_url: Url::parse("https://kaylynn.gay").unwrap(),
+            title: "Kaylynn's blog".to_owned(),
+            description: "Computers, cats, and eternal sleepiness".to_owned(),
+            copyright: "Copyright 2021-present, Kaylynn Morgan".to_owned(),
+            logo: "/favicon.png".to_owned(),
+            author: AuthorConfig {
+                name: "Kaylynn Morgan".to_owned(),
+                email: "mkaylynn7@gmail.com".to_owned(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorConfig {
+    name: String,
+    email: String,
+}
+
+impl AuthorConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+}
+
+/// Options that control how Markdown is rendered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    heading_anchors: bool,
+    words_per_minute: NonZeroUsize,
+    language_aliases: IndexMap<String, String>,
+    extensions: ExtensionsConfig,
+}
+
+impl MarkdownConfig {
+    /// Whether headings should include a link to themselves.
+    pub fn heading_anchors(&self) -> bool {
+        self.heading_anchors
+    }
+
+    /// How quickly people are assumed to read, when estimating how long a post takes to read.
+    pub fn words_per_minute(&self) -> usize {
+        self.words_per_minute.get()
+    }
+
+    /// Alternative names for code block languages, mapped to the name or file extension of a highlighting syntax.
+    pub fn language_aliases(&self) -> &IndexMap<String, String> {
+        &self.language_aliases
+    }
+
+    pub fn extensions(&self) -> &ExtensionsConfig {
+        &self.extensions
+    }
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            heading_anchors: false,
+            words_per_minute: NonZeroUsize::new(200).unwrap(),
+            // `sh`, `bash` and `rs` already match a syntax by file extension, but the bundled Rust syntax is called
+            // "Rust Enhanced" and no syntax is called "Shell", so these two names need a hand.
+            language_aliases: [("rust", "rs"), ("shell", "sh")]
+                .into_iter()
+                .map(|(alias, language)| (alias.to_owned(), language.to_owned()))
+                .collect(),
+            extensions: ExtensionsConfig::default(),
+        }
+    }
+}
+
+/// The extensions to CommonMark that are enabled when rendering Markdown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtensionsConfig {
+    strikethrough: bool,
+    table: bool,
+    autolink: bool,
+    tasklist: bool,
+    description_lists: bool,
+    footnotes: bool,
+    superscript: bool,
+    admonitions: bool,
+}
+
+impl ExtensionsConfig {
+    /// Whether `~~text~~` is struck through.
+    pub fn strikethrough(&self) -> bool {
+        self.strikethrough
+    }
+
+    /// Whether GitHub-style tables are allowed.
+    pub fn table(&self) -> bool {
+        self.table
+    }
+
+    /// Whether bare URLs are turned into links.
+    pub fn autolink(&self) -> bool {
+        self.autolink
+    }
+
+    /// Whether `- [ ]` list items are turned into checkboxes.
+    pub fn tasklist(&self) -> bool {
+        self.tasklist
+    }
+
+    pub fn description_lists(&self) -> bool {
+        self.description_lists
+    }
+
+    /// Whether `[^name]` references footnotes, which are listed at the end of the document.
+    pub fn footnotes(&self) -> bool {
+        self.footnotes
+    }
+
+    /// Whether `^text^` is written in superscript.
+    ///
+    /// This is off by default, since comrak reads the `^` in footnote references as the start of a superscript.
+    pub fn superscript(&self) -> bool {
+        self.superscript
+    }
+
+    /// Whether `:::note` and `:::warning` blocks are turned into callouts.
+    pub fn admonitions(&self) -> bool {
+        self.admonitions
+    }
+}
+
+impl Default for ExtensionsConfig {
+    fn default() -> Self {
+        Self {
+            strikethrough: true,
+            table: true,
+            autolink: true,
+            tasklist: true,
+            description_lists: true,
+            footnotes: true,
+            superscript: false,
+            admonitions: true,
+        }
+    }
+}
+
+/// How images in Markdown are resized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImagesConfig {
+    widths: Vec<u32>,
+    formats: Vec<ImageFormat>,
+    quality: u8,
+    sizes: String,
+    cache_dir: PathBuf,
+}
+
+impl ImagesConfig {
+    /// The widths that each image is resized to. Images are never made larger than they already are.
+    pub fn widths(&self) -> &[u32] {
+        &self.widths
+    }
+
+    /// The formats that resized images are written in, from most to least preferred.
+    pub fn formats(&self) -> &[ImageFormat] {
+        &self.formats
+    }
+
+    /// The quality of resized images, from 0 to 100.
+    pub fn quality(&self) -> u8 {
+        self.quality.min(100)
+    }
+
+    /// The value of the `sizes` attribute, which tells browsers how wide images are shown before they're loaded.
+    pub fn sizes(&self) -> &str {
+        &self.sizes
+    }
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            widths: vec![480, 960, 1440],
+            formats: vec![ImageFormat::Avif, ImageFormat::Webp],
+            quality: 80,
+            // This matches the width of `body` in `style.css`.
+            sizes: "(max-width: 1000px) 87.5vw, 77.5vw".to_owned(),
+            cache_dir: PathBuf::from("image-cache"),
+        }
+    }
+}
+
+/// A format that resized images can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Avif,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Avif => "avif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// The highlighting themes used for `/highlight.css`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighlightConfig {
+    light: Option<String>,
+    dark: Option<String>,
+}
+
+impl HighlightConfig {
+    /// The name of the theme used when the reader prefers a light colour scheme.
+    pub fn light(&self) -> Option<&str> {
+        self.light.as_deref()
+    }
+
+    /// The name of the theme used when the reader prefers a dark colour scheme.
+    pub fn dark(&self) -> Option<&str> {
+        self.dark.as_deref()
+    }
+}
+
+/// Options for the `robots.txt` served by the site.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RobotsConfig {
+    disallow: Vec<String>,
+}
+
+impl RobotsConfig {
+    /// The paths that crawlers are asked to stay away from.
+    pub fn disallow(&self) -> &[String] {
+        &self.disallow
+    }
+}
+
+/// Options for each of the feeds served under `/blog`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedsConfig {
+    rss: FeedConfig,
+    atom: FeedConfig,
+    json: FeedConfig,
+}
+
+impl FeedsConfig {
+    pub fn rss(&self) -> &FeedConfig {
+        &self.rss
+    }
+
+    pub fn atom(&self) -> &FeedConfig {
+        &self.atom
+    }
+
+    pub fn json(&self) -> &FeedConfig {
+        &self.json
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedConfig {
+    full_content: bool,
+}
+
+impl FeedConfig {
+    /// Whether entries in the feed should include the full content of each post, rather than just a summary.
+    pub fn full_content(&self) -> bool {
+        self.full_content
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct State(Arc<StateInner>);
 
 impl State {
     pub fn try_new(config: Config) -> Result<Self> {
         let mut posts = Posts::new();
-        posts.refresh(&config.content_dir.join("blog-pages"))?;
-
-        let engine = Engine::new({
-            let mut tera = Tera::new(
-                &config
-                    .content_dir
-                    .join("templates/*.html.tera")
-                    .to_string_lossy(),
-            )?;
+        posts.refresh(&config)?;
 
-            tera.register_filter("humanize", templates::humanize);
-            tera
-        });
+        let engine = Engine::load(&config.content_dir.join("templates"), config.site.context())?;
+        let themes = Themes::load(&config.content_dir.join("themes"), &config.highlight)?;
 
         let inner = StateInner {
             config,
-            engine,
-            posts,
+            engine: RwLock::new(Arc::new(engine)),
+            posts: RwLock::new(Arc::new(posts)),
+            themes: RwLock::new(Arc::new(themes)),
+            reloading: Mutex::new(()),
         };
 
         Ok(State(Arc::new(inner)))
@@ -103,12 +520,91 @@ impl State {
         &self.0.config
     }
 
-    pub fn engine(&self) -> &Engine {
-        &self.0.engine
+    /// Return the template engine that is currently in use.
+    ///
+    /// The returned handle is not affected by later reloads, so it can be held onto for the duration of a request.
+    pub fn engine(&self) -> Arc<Engine> {
+        self.0.engine.read().unwrap().clone()
     }
 
-    pub fn posts(&self) -> &Posts {
-        &self.0.posts
+    /// Return the posts that are currently being served.
+    ///
+    /// Like `State::engine`, the returned handle is not affected by later reloads.
+    pub fn posts(&self) -> Arc<Posts> {
+        self.0.posts.read().unwrap().clone()
+    }
+
+    /// Return the highlighting stylesheets that are currently being served.
+    ///
+    /// Like `State::engine`, the returned handle is not affected by later reloads.
+    pub fn themes(&self) -> Arc<Themes> {
+        self.0.themes.read().unwrap().clone()
+    }
+
+    /// Re-read posts from the content directory and swap them in.
+    ///
+    /// Requests that are already in flight keep using the previous posts. Only one reload happens at a time, so a slow
+    /// reload can't swap in posts that are older than the ones from a reload that started after it.
+    pub fn reload_posts(&self) -> Result<RefreshReport> {
+        let _reloading = self.0.reloading.lock().unwrap();
+        let mut posts = Posts::clone(&self.posts());
+        let report = posts.refresh(self.config())?;
+
+        *self.0.posts.write().unwrap() = Arc::new(posts);
+
+        Ok(report)
+    }
+
+    /// Re-load templates from the content directory and swap them in.
+    ///
+    /// The previous templates are left in place if loading fails.
+    pub fn reload_templates(&self) -> Result<()> {
+        let engine = Engine::load(
+            &self.config().content_dir().join("templates"),
+            self.config().site().context(),
+        )?;
+
+        *self.0.engine.write().unwrap() = Arc::new(engine);
+
+        Ok(())
+    }
+
+    /// Re-load highlighting themes from the content directory and swap them in.
+    ///
+    /// The previous themes are left in place if loading fails.
+    pub fn reload_themes(&self) -> Result<()> {
+        let themes = Themes::load(
+            &self.config().content_dir().join("themes"),
+            self.config().highlight(),
+        )?;
+
+        *self.0.themes.write().unwrap() = Arc::new(themes);
+
+        Ok(())
+    }
+
+    /// Re-load highlighting syntaxes from the content directory.
+    ///
+    /// Posts are highlighted when they're imported, so they're reloaded afterwards.
+    pub fn reload_syntaxes(&self) -> Result<RefreshReport> {
+        markdown::load_syntaxes(&self.config().content_dir().join("syntaxes"))?;
+
+        self.reload_posts()
+    }
+
+    /// Re-load shortcode templates from the content directory.
+    ///
+    /// Shortcodes are expanded when posts are imported, so they're reloaded afterwards.
+    pub fn reload_shortcodes(&self) -> Result<RefreshReport> {
+        markdown::load_shortcodes(
+            &self
+                .config()
+                .content_dir()
+                .join("templates")
+                .join("shortcodes"),
+        )?;
+
+        self.reload_posts()
     }
 }
 
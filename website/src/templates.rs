@@ -1,7 +1,7 @@
 use axum::response::Html;
 use chrono::{self, DateTime, Local};
 use chrono_humanize::{Accuracy, HumanTime, Tense};
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 use tera::{Context, Error, Result, Tera, Value};
 
 #[macro_export]
@@ -16,15 +16,33 @@ macro_rules! context {
 
 /// The template engine used for rendering templates.
 #[derive(Debug)]
-pub struct Engine(Tera);
+pub struct Engine {
+    tera: Tera,
+    globals: Context,
+}
 
 impl Engine {
-    pub fn new(tera: Tera) -> Self {
-        Self(tera)
+    /// Create a new engine. Every template rendered by it has access to `globals`.
+    pub fn new(tera: Tera, globals: Context) -> Self {
+        Self { tera, globals }
     }
 
+    /// Load every template in `directory`, and register the filters used by them.
+    pub fn load(directory: &Path, globals: Context) -> Result<Self> {
+        let mut tera = Tera::new(&directory.join("*.html.tera").to_string_lossy())?;
+        tera.register_filter("humanize", humanize);
+
+        Ok(Self::new(tera, globals))
+    }
+
+    /// Render the template `template_name`.
+    ///
+    /// Values in `context` take priority over global values with the same name.
     pub fn render(&self, template_name: &str, context: &Context) -> Result<Html<String>> {
-        self.0.render(template_name, context).map(Html)
+        let mut full_context = self.globals.clone();
+        full_context.extend(context.clone());
+
+        self.tera.render(template_name, &full_context).map(Html)
     }
 }
 
@@ -0,0 +1,86 @@
+use indexmap::IndexMap;
+use std::path::Path;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{self, ClassStyle},
+};
+
+use crate::{
+    error::{Error, Result},
+    state::HighlightConfig,
+};
+
+/// Stylesheets generated from highlighting themes, to go with the classes used by `markdown::highlight`.
+#[derive(Debug, Default)]
+pub struct Themes {
+    stylesheets: IndexMap<String, String>,
+    combined: String,
+}
+
+impl Themes {
+    /// Load the themes bundled with syntect, along with any `.tmTheme` files in `directory`.
+    ///
+    /// Themes in `directory` replace bundled themes with the same name. The directory doesn't need to exist.
+    ///
+    /// This function returns an error if a theme fails to load, or if a theme named in `config` doesn't exist.
+    pub fn load(directory: &Path, config: &HighlightConfig) -> Result<Self> {
+        let mut theme_set = ThemeSet::load_defaults();
+
+        if directory.is_dir() {
+            theme_set.add_from_folder(directory)?;
+        }
+
+        let stylesheets = theme_set
+            .themes
+            .iter()
+            .map(|(name, theme)| Ok((name.clone(), stylesheet(theme)?)))
+            .collect::<Result<IndexMap<_, _>>>()?;
+
+        let mut combined = String::new();
+
+        for (scheme, name) in [("light", config.light()), ("dark", config.dark())] {
+            let Some(name) = name else {
+                continue;
+            };
+
+            let css = stylesheets.get(name).ok_or_else(|| {
+                Error::msg(format!(
+                    "the {scheme} highlighting theme `{name}` doesn't exist"
+                ))
+            })?;
+
+            combined.push_str(&format!(
+                "@media (prefers-color-scheme: {scheme}) {{\n{css}}}\n"
+            ));
+        }
+
+        Ok(Self {
+            stylesheets,
+            combined,
+        })
+    }
+
+    /// Return the stylesheet for the theme called `name`, if it exists.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.stylesheets.get(name).map(String::as_str)
+    }
+
+    /// Iterate over the name and stylesheet of each theme.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.stylesheets
+            .iter()
+            .map(|(name, css)| (name.as_str(), css.as_str()))
+    }
+
+    /// Return a stylesheet that uses the configured light and dark themes, depending on the reader's preference.
+    ///
+    /// This is empty if neither theme is configured, so the highlighting colours in `style.css` are left alone.
+    pub fn combined(&self) -> &str {
+        &self.combined
+    }
+}
+
+fn stylesheet(theme: &Theme) -> Result<String> {
+    html::css_for_theme_with_class_style(theme, ClassStyle::SpacedPrefixed { prefix: "hl-" })
+        .map_err(|error| Error::msg(format!("failed to generate stylesheet: {error}")))
+}
@@ -0,0 +1,133 @@
+use bitflags::bitflags;
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::{path::PathBuf, time::Duration};
+use tracing::{event, Level};
+
+use crate::{error::Result, state::State};
+
+/// How long to wait for the filesystem to settle before rebuilding anything.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(250);
+
+bitflags! {
+    /// The parts of the content directory that have changed since the last rebuild.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Changes: u8 {
+        const POSTS = 1 << 0;
+        const PAGES = 1 << 1;
+        const TEMPLATES = 1 << 2;
+        const SYNTAXES = 1 << 3;
+        const THEMES = 1 << 4;
+        const SHORTCODES = 1 << 5;
+    }
+}
+
+/// Watch the content directory for changes, and rebuild the affected parts of `state` as they happen.
+///
+/// Content is only watched for as long as the returned `Debouncer` is kept alive.
+pub fn watch(state: State) -> Result<Debouncer<RecommendedWatcher>> {
+    let content_dir = state.config().content_dir();
+    let directories: Vec<(PathBuf, Changes)> = vec![
+        (content_dir.join("blog-pages"), Changes::POSTS),
+        (content_dir.join("pages"), Changes::PAGES),
+        (content_dir.join("templates"), Changes::TEMPLATES),
+        (content_dir.join("syntaxes"), Changes::SYNTAXES),
+        (content_dir.join("themes"), Changes::THEMES),
+        (
+            content_dir.join("templates").join("shortcodes"),
+            Changes::SHORTCODES,
+        ),
+    ];
+
+    let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, {
+        let directories = directories.clone();
+
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let changes = events
+                    .iter()
+                    .flat_map(|event| {
+                        directories
+                            .iter()
+                            .filter(|(directory, _)| event.path.starts_with(directory))
+                            .map(|&(_, changes)| changes)
+                    })
+                    .fold(Changes::empty(), Changes::union);
+
+                rebuild(&state, changes);
+            }
+            Err(error) => event!(
+                Level::ERROR,
+                error = &error as &dyn std::error::Error,
+                "Error watching content directory"
+            ),
+        }
+    })?;
+
+    // Not every directory is required (there may not be any custom themes, for example), so missing ones are skipped.
+    for (directory, _) in directories
+        .iter()
+        .filter(|(directory, _)| directory.is_dir())
+    {
+        debouncer
+            .watcher()
+            .watch(directory, RecursiveMode::Recursive)?;
+    }
+
+    Ok(debouncer)
+}
+
+#[tracing::instrument(skip(state))]
+fn rebuild(state: &State, changes: Changes) {
+    // Syntaxes and shortcodes are used when importing posts, so reloading either of them also reloads posts.
+    let result = if changes.contains(Changes::SYNTAXES | Changes::SHORTCODES) {
+        state
+            .reload_syntaxes()
+            .and_then(|_| state.reload_shortcodes())
+            .map(drop)
+    } else if changes.contains(Changes::SYNTAXES) {
+        state.reload_syntaxes().map(drop)
+    } else if changes.contains(Changes::SHORTCODES) {
+        state.reload_shortcodes().map(drop)
+    } else if changes.contains(Changes::POSTS) {
+        state.reload_posts().map(drop)
+    } else {
+        Ok(())
+    };
+
+    if let Err(error) = result {
+        event!(
+            Level::ERROR,
+            error = &error as &dyn std::error::Error,
+            "Unable to reload posts, keeping previous version"
+        );
+    }
+
+    if changes.contains(Changes::TEMPLATES) {
+        match state.reload_templates() {
+            Ok(()) => event!(Level::INFO, "Reloaded templates"),
+            Err(error) => event!(
+                Level::ERROR,
+                error = &error as &dyn std::error::Error,
+                "Unable to reload templates, keeping previous version"
+            ),
+        }
+    }
+
+    if changes.contains(Changes::THEMES) {
+        match state.reload_themes() {
+            Ok(()) => event!(Level::INFO, "Reloaded highlighting themes"),
+            Err(error) => event!(
+                Level::ERROR,
+                error = &error as &dyn std::error::Error,
+                "Unable to reload highlighting themes, keeping previous version"
+            ),
+        }
+    }
+
+    // Pages are read from disk whenever they're requested, so there's nothing to rebuild. It's still worth noting that
+    // something happened, though.
+    if changes.contains(Changes::PAGES) {
+        event!(Level::INFO, "Pages changed");
+    }
+}